@@ -0,0 +1,306 @@
+//! Direct Memory Access controller
+//!
+//! The controller has 4 channels without a circular mode. Circular transfers are emulated
+//! by restarting the channel from [`on_interrupt`], which must be called from the trap
+//! handler whenever the EPIC reports the DMA line.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use mik32v2_pac::{Dma, Epic, Pm};
+
+use crate::rcc::Enable;
+
+// `CHx_CFG` bits. The SVD puts `WRITE_INCREMENT` on bit 5 together with `READ_INCREMENT`,
+// so the configuration is written as raw bits.
+const CFG_ENABLE: u32 = 1 << 0;
+const CFG_PRIOR_SHIFT: u32 = 1;
+const CFG_READ_MEMORY: u32 = 1 << 3;
+const CFG_WRITE_MEMORY: u32 = 1 << 4;
+const CFG_READ_INCREMENT: u32 = 1 << 5;
+const CFG_WRITE_INCREMENT: u32 = 1 << 6;
+const CFG_READ_REQUEST_SHIFT: u32 = 17;
+const CFG_WRITE_REQUEST_SHIFT: u32 = 21;
+const CFG_IRQ_EN: u32 = 1 << 27;
+
+// `CONFIG` bits. The register is write-only, the enables are written with every clear.
+const CONFIG_CLEAR_GLOBAL_IRQ: u32 = 1 << 4;
+const CONFIG_GLOBAL_IRQ_ENA: u32 = 1 << 6;
+
+// `STATUS` bits
+const STATUS_CHANNEL_IRQ_SHIFT: u32 = 4;
+
+/// Peripheral request line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Request {
+    Usart0 = 0,
+    Usart1 = 1,
+    Crypto = 2,
+    Spi0 = 3,
+    Spi1 = 4,
+    I2c0 = 5,
+    I2c1 = 6,
+    Spifi = 7,
+    Timer32_1 = 8,
+    Timer32_2 = 9,
+    Dac0 = 10,
+    Dac1 = 11,
+    Timer32_0 = 12,
+}
+
+/// Channel priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Priority {
+    Low = 0,
+    Medium = 1,
+    High = 2,
+    VeryHigh = 3,
+}
+
+/// Source or destination of a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    /// Memory, the address is incremented after each byte
+    Memory,
+    /// Peripheral register, paced by the request line
+    Peripheral(Request),
+}
+
+/// Byte transfer configuration
+#[derive(Debug, Clone, Copy)]
+pub struct TransferConfig {
+    pub read: Endpoint,
+    pub write: Endpoint,
+    pub priority: Priority,
+}
+
+impl TransferConfig {
+    fn bits(&self) -> u32 {
+        let mut cfg = (self.priority as u32) << CFG_PRIOR_SHIFT;
+        cfg |= match self.read {
+            Endpoint::Memory => CFG_READ_MEMORY | CFG_READ_INCREMENT,
+            Endpoint::Peripheral(request) => (request as u32) << CFG_READ_REQUEST_SHIFT,
+        };
+        cfg |= match self.write {
+            Endpoint::Memory => CFG_WRITE_MEMORY | CFG_WRITE_INCREMENT,
+            Endpoint::Peripheral(request) => (request as u32) << CFG_WRITE_REQUEST_SHIFT,
+        };
+        cfg
+    }
+}
+
+/// Channel state shared with the interrupt handler for circular transfers
+struct Circular {
+    active: AtomicBool,
+    laps: AtomicU32,
+    src: AtomicU32,
+    dst: AtomicU32,
+    len: AtomicU32,
+    cfg: AtomicU32,
+}
+
+impl Circular {
+    const fn new() -> Self {
+        Self {
+            active: AtomicBool::new(false),
+            laps: AtomicU32::new(0),
+            src: AtomicU32::new(0),
+            dst: AtomicU32::new(0),
+            len: AtomicU32::new(0),
+            cfg: AtomicU32::new(0),
+        }
+    }
+}
+
+static CIRCULAR: [Circular; 4] = [Circular::new(), Circular::new(), Circular::new(), Circular::new()];
+
+/// Writes the interrupt clear bits of `CONFIG`, keeping the global interrupt enabled
+fn write_config(clear: u32) {
+    // NOTE(unsafe) the enables are the same for every write
+    unsafe { (*Dma::ptr()).config().write(|w| w.bits(clear | CONFIG_GLOBAL_IRQ_ENA)) };
+}
+
+/// Number of times the circular transfer on channel `index` wrapped around
+pub(crate) fn circular_laps(index: usize) -> u32 {
+    CIRCULAR[index].laps.load(Ordering::Acquire)
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A DMA channel
+///
+/// This trait is sealed, it is only implemented for the channels of [`Channels`].
+pub trait Channel: sealed::Sealed {
+    /// Index of the channel, 0 for `C1`
+    const INDEX: usize;
+
+    /// Starts a transfer of `len` bytes from `src` to `dst`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is 0, `CHx_LEN` holds the length minus one.
+    ///
+    /// # Safety
+    ///
+    /// `src` and `dst` must stay valid for `len` bytes until the transfer completes or
+    /// is stopped.
+    unsafe fn start(&mut self, src: u32, dst: u32, len: usize, config: TransferConfig) {
+        assert!(len > 0, "a DMA transfer moves at least one byte");
+        CIRCULAR[Self::INDEX].active.store(false, Ordering::Relaxed);
+        program(Self::INDEX, src, dst, len as u32, config.bits());
+    }
+
+    /// Starts a transfer that restarts from the beginning whenever it completes
+    ///
+    /// The EPIC DMA line is unmasked, [`on_interrupt`] restarts the channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is 0.
+    ///
+    /// # Safety
+    ///
+    /// `src` and `dst` must stay valid for `len` bytes until the transfer is stopped.
+    unsafe fn start_circular(&mut self, src: u32, dst: u32, len: usize, config: TransferConfig) {
+        assert!(len > 0, "a DMA transfer moves at least one byte");
+        let circular = &CIRCULAR[Self::INDEX];
+        let cfg = config.bits() | CFG_IRQ_EN;
+        circular.src.store(src, Ordering::Relaxed);
+        circular.dst.store(dst, Ordering::Relaxed);
+        circular.len.store(len as u32, Ordering::Relaxed);
+        circular.cfg.store(cfg, Ordering::Relaxed);
+        circular.laps.store(0, Ordering::Relaxed);
+        circular.active.store(true, Ordering::Release);
+
+        // NOTE(unsafe) atomic writes to stateless registers
+        Epic::enable(unsafe { &*Pm::ptr() });
+        unsafe { (*Epic::ptr()).mask_level_set().write(|w| w.dma().set_bit()) };
+        program(Self::INDEX, src, dst, len as u32, cfg);
+    }
+
+    /// Stops the channel, a circular transfer is not restarted anymore
+    fn stop(&mut self);
+
+    /// Returns `true` while a transfer is running
+    fn is_busy(&self) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        let ready = unsafe { (*Dma::ptr()).status().read().channel_ready().bits() };
+        ready & (1 << Self::INDEX) == 0
+    }
+
+    /// Number of bytes written by the running transfer
+    ///
+    /// `CHx_LEN` reads back the byte counter of the write side, since `CONFIG` is always
+    /// written with `CURRENT_VALUE = 0`.
+    fn transferred(&self) -> usize;
+}
+
+macro_rules! dma_channels {
+    ($(
+        $Cx:ident: ($index:literal, $chx_dst:ident, $chx_src:ident, $chx_len:ident, $chx_cfg:ident),
+    )+) => {
+        /// DMA channels
+        pub struct Channels($(pub $Cx),+);
+
+        $(
+            /// DMA channel
+            pub struct $Cx {
+                _0: (),
+            }
+
+            impl sealed::Sealed for $Cx {}
+
+            impl Channel for $Cx {
+                const INDEX: usize = $index;
+
+                fn stop(&mut self) {
+                    CIRCULAR[$index].active.store(false, Ordering::Release);
+                    // NOTE(unsafe) the channel registers are owned by this channel
+                    unsafe { (*Dma::ptr()).$chx_cfg().write(|w| w.bits(0)) };
+                }
+
+                fn transferred(&self) -> usize {
+                    // NOTE(unsafe) atomic read with no side effects
+                    unsafe { (*Dma::ptr()).$chx_len().read().bits() as usize }
+                }
+            }
+        )+
+
+        /// Programs and enables channel `index`
+        fn program(index: usize, src: u32, dst: u32, len: u32, cfg: u32) {
+            // NOTE(unsafe) the channel registers are owned by the channel or its interrupt
+            let dma = unsafe { &*Dma::ptr() };
+            match index {
+                $(
+                    $index => unsafe {
+                        dma.$chx_cfg().write(|w| w.bits(0));
+                        dma.$chx_src().write(|w| w.bits(src));
+                        dma.$chx_dst().write(|w| w.bits(dst));
+                        dma.$chx_len().write(|w| w.bits(len - 1));
+                        dma.$chx_cfg().write(|w| w.bits(cfg | CFG_ENABLE));
+                    },
+                )+
+                _ => unreachable!(),
+            }
+        }
+
+        impl DmaExt for Dma {
+            type Channels = Channels;
+
+            fn split(self) -> Channels {
+                // NOTE(unsafe) This executes only during initialisation
+                let pm = unsafe { &(*Pm::ptr()) };
+                Dma::enable(pm);
+                write_config(0b1111 | CONFIG_CLEAR_GLOBAL_IRQ);
+
+                Channels($($Cx { _0: () }),+)
+            }
+        }
+    };
+}
+
+/// Extension trait to split the DMA controller into its channels
+pub trait DmaExt {
+    type Channels;
+
+    fn split(self) -> Self::Channels;
+}
+
+dma_channels! {
+    C1: (0, ch1_dst, ch1_src, ch1_len, ch1_cfg),
+    C2: (1, ch2_dst, ch2_src, ch2_len, ch2_cfg),
+    C3: (2, ch3_dst, ch3_src, ch3_len, ch3_cfg),
+    C4: (3, ch4_dst, ch4_src, ch4_len, ch4_cfg),
+}
+
+/// Handles the DMA interrupt, restarting the completed circular transfers
+pub fn on_interrupt() {
+    // NOTE(unsafe) only called from the interrupt handler
+    let status = unsafe { (*Dma::ptr()).status().read().bits() };
+
+    for (index, circular) in CIRCULAR.iter().enumerate() {
+        if status & (1 << (STATUS_CHANNEL_IRQ_SHIFT + index as u32)) == 0 {
+            continue;
+        }
+        write_config(1 << index);
+
+        if circular.active.load(Ordering::Acquire) {
+            program(
+                index,
+                circular.src.load(Ordering::Relaxed),
+                circular.dst.load(Ordering::Relaxed),
+                circular.len.load(Ordering::Relaxed),
+                circular.cfg.load(Ordering::Relaxed),
+            );
+            let laps = circular.laps.load(Ordering::Relaxed);
+            circular.laps.store(laps.wrapping_add(1), Ordering::Release);
+        }
+    }
+    write_config(CONFIG_CLEAR_GLOBAL_IRQ);
+
+    // NOTE(unsafe) atomic write to a stateless register
+    unsafe { (*Epic::ptr()).clear().write(|w| w.dma().set_bit()) };
+}
@@ -1,10 +1,16 @@
 //! General Purpose Input / Output
  
 use core::marker::PhantomData;
+pub mod alt;
 mod partially_erased;
 mod convert;
+mod erased;
 mod emb_hal;
+mod exti;
+pub mod wait;
 use mik32v2_pac::timer32_0::value;
+pub use erased::{AnyPin, ErasedPin};
+pub use exti::{Edge, ExtiPin, InvalidLine, NotRouted};
 pub use partially_erased::{PEPin, PartiallyErasedPin};
  
 /// Extension trait to split a GPIO peripheral in independent pins and registers
@@ -47,6 +53,54 @@ pub enum PinState {
     High,
 }
 
+/// Output drive strength of a pad
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+pub enum DriveStrength {
+    /// 2 mA
+    Low = 0b00,
+    /// 4 mA
+    Medium = 0b01,
+    /// 8 mA
+    High = 0b10,
+}
+
+/// Writes the drive strength of pin `i` of port `port` into `PADx_DS`
+fn set_drive_strength(port: u8, i: u8, strength: DriveStrength) {
+    let mask = 0b11 << 2 * i;
+    let value = (strength as u32) << 2 * i;
+    // NOTE(unsafe) the pad bits of a pin are owned by the pin
+    unsafe {
+        match port {
+            0 => (*mik32v2_pac::PadConfig::ptr()).pad0_ds()
+            .modify(|r, w| w.bits((r.bits() & !mask) | value)),
+            1 => (*mik32v2_pac::PadConfig::ptr()).pad1_ds()
+            .modify(|r, w| w.bits((r.bits() & !mask) | value)),
+            2 => (*mik32v2_pac::PadConfig::ptr()).pad2_ds()
+            .modify(|r, w| w.bits((r.bits() & !mask) | value)),
+            _ => panic!("Invalid GPIO port number: {}", port)
+        }
+    };
+}
+
+/// Writes the 2 bit pad function of a pin, `0b00` selects the GPIO
+fn set_pad_function(port: u8, i: u8, value: u32) {
+    let mask = 0b11 << 2 * i;
+    let value = value << 2 * i;
+    // NOTE(unsafe) the pad bits of a pin are owned by the pin
+    unsafe {
+        match port {
+            0 => (*mik32v2_pac::PadConfig::ptr()).pad0_cfg()
+            .modify(|r, w| w.bits((r.bits() & !mask) | value)),
+            1 => (*mik32v2_pac::PadConfig::ptr()).pad1_cfg()
+            .modify(|r, w| w.bits((r.bits() & !mask) | value)),
+            2 => (*mik32v2_pac::PadConfig::ptr()).pad2_cfg()
+            .modify(|r, w| w.bits((r.bits() & !mask) | value)),
+            _ => panic!("Invalid GPIO port number: {}", port)
+        }
+    };
+}
+
 impl<const P: u8, const N: u8, MODE> Pin<P, N, MODE> {
     /// Set the output of the pin regardless of its mode.
     /// Primarily used to set the output value of the pin
@@ -85,6 +139,11 @@ impl<const P: u8, const N: u8, MODE> Pin<P, N, MODE> {
     pub fn erase_number(self) -> PEPin<P, MODE> {
         PEPin::new(N)
     }
+
+    /// Sets the output drive strength of the pad
+    pub fn set_drive_strength(&mut self, strength: DriveStrength) {
+        set_drive_strength(P, N, strength);
+    }
 }
 
 impl<const P: u8, const N: u8, MODE> PinExt for Pin<P, N, MODE> {
@@ -178,11 +237,21 @@ pub struct PullUp;
 /// Output mode (type state)
 pub struct Output;
 
+/// Alternate function mode (type state)
+///
+/// `F` is the pad function number of the reference manual, 2 or 3. Function 1 is the
+/// GPIO itself.
+pub struct Alternate<const F: u8>;
+
+/// Analog mode (type state), pad function 4
+pub struct Analog;
+
 /// Func2Mode mode (type state)
-pub struct Func2Mode;
+#[deprecated(note = "use `Alternate<2>`")]
+pub type Func2Mode = Alternate<2>;
 
 macro_rules! gpio {
-    ($GPIOX:ident, $gpiox:ident, $iopxenr:ident, $port_id:expr, $PXn:ident, [
+    ($GPIOX:ident, $gpiox:ident, $port_id:expr, $PXn:ident, [
         $($PXi:ident: ($pxi:ident, $i:expr $(, $MODE:ty)?),)+
     ]) => {
         /// GPIO
@@ -191,6 +260,7 @@ macro_rules! gpio {
             use mik32v2_pac::$GPIOX;
 
             use mik32v2_pac::Pm;
+            use crate::rcc::Enable;
             use super::{GpioExt, Input, Floating};
 
             /// GPIO parts
@@ -207,7 +277,7 @@ macro_rules! gpio {
                 fn split(self) -> Parts {
                     // NOTE(unsafe) This executes only during initialisation
                     let pm = unsafe { &(*Pm::ptr()) };
-                    pm.clk_apb_p_set().modify(|_, w| w.$iopxenr().set_bit());
+                    $GPIOX::enable(pm);
 
                     Parts {
                         $(
@@ -228,7 +298,7 @@ macro_rules! gpio {
     }
 }
 
-gpio!(Gpio16_0, gpio16_0, gpio_0, 0, P16_0_n, [
+gpio!(Gpio16_0, gpio16_0, 0, P16_0_n, [
     P16_0_0: (p16_0_0, 0),
     P16_0_1: (p16_0_1, 1),
     P16_0_2: (p16_0_2, 2),
@@ -247,7 +317,7 @@ gpio!(Gpio16_0, gpio16_0, gpio_0, 0, P16_0_n, [
     P16_0_15: (p16_0_15, 15),
 ]);
 
-gpio!(Gpio16_1, gpio16_1, gpio_1, 1, P16_1_n, [
+gpio!(Gpio16_1, gpio16_1, 1, P16_1_n, [
     P16_1_0: (p16_1_0, 0),
     P16_1_1: (p16_1_1, 1),
     P16_1_2: (p16_1_2, 2),
@@ -266,7 +336,7 @@ gpio!(Gpio16_1, gpio16_1, gpio_1, 1, P16_1_n, [
     P16_1_15: (p16_1_15, 15),
 ]);
 
-gpio!(Gpio8_2, gpio8_2, gpio_2, 2, P8_2_n, [
+gpio!(Gpio8_2, gpio8_2, 2, P8_2_n, [
     P8_2_0: (p8_2_0, 0),
     P8_2_1: (p8_2_1, 1),
     P8_2_2: (p8_2_2, 2),
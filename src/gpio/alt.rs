@@ -0,0 +1,116 @@
+//! Pad functions of the peripheral signals
+//!
+//! Drivers require these traits on their pin arguments, so a pin has to be switched to the
+//! matching pad function with [`into_alternate`](super::Pin::into_alternate) or
+//! [`into_analog`](super::Pin::into_analog) before it is accepted. The USART signals are
+//! mapped in [`serial`](crate::serial).
+
+use mik32v2_pac::{
+    Adc, Dac0, Dac1, I2c0, I2c1, SpifiConfig, Spi0, Spi1, Timer16_0, Timer16_1, Timer16_2,
+    Timer32_1, Timer32_2,
+};
+
+use super::{
+    Alternate, Analog, P16_0_0, P16_0_1, P16_0_10, P16_0_11, P16_0_12, P16_0_13, P16_0_2,
+    P16_0_3, P16_0_4, P16_0_5, P16_0_6, P16_0_7, P16_0_8, P16_0_9, P16_1_0, P16_1_1, P16_1_12, P16_1_13, P16_1_2, P16_1_3,
+    P16_1_4, P16_1_5, P16_1_7, P8_2_0, P8_2_1, P8_2_2, P8_2_3, P8_2_4, P8_2_5,
+};
+
+/// SPI clock
+pub trait PinSck<SPI> {}
+/// SPI master input, slave output
+pub trait PinMiso<SPI> {}
+/// SPI master output, slave input
+pub trait PinMosi<SPI> {}
+/// SPI chip select input of the slave mode
+pub trait PinNss<SPI> {}
+/// SPI chip select output 0 of the master mode
+pub trait PinCs0<SPI> {}
+
+impl PinMiso<Spi0> for P16_0_0<Alternate<2>> {}
+impl PinMosi<Spi0> for P16_0_1<Alternate<2>> {}
+impl PinSck<Spi0> for P16_0_2<Alternate<2>> {}
+impl PinNss<Spi0> for P16_0_3<Alternate<2>> {}
+impl PinCs0<Spi0> for P16_0_4<Alternate<2>> {}
+
+impl PinMiso<Spi1> for P16_1_0<Alternate<2>> {}
+impl PinMosi<Spi1> for P16_1_1<Alternate<2>> {}
+impl PinSck<Spi1> for P16_1_2<Alternate<2>> {}
+impl PinNss<Spi1> for P16_1_3<Alternate<2>> {}
+impl PinCs0<Spi1> for P16_1_4<Alternate<2>> {}
+
+/// I2C clock
+pub trait PinScl<I2C> {}
+/// I2C data
+pub trait PinSda<I2C> {}
+
+impl PinSda<I2c0> for P16_0_9<Alternate<2>> {}
+impl PinScl<I2c0> for P16_0_10<Alternate<2>> {}
+
+impl PinSda<I2c1> for P16_1_12<Alternate<2>> {}
+impl PinScl<I2c1> for P16_1_13<Alternate<2>> {}
+
+/// SPIFI clock
+pub trait PinSpifiSck<SPIFI> {}
+/// SPIFI chip select
+pub trait PinSpifiCs<SPIFI> {}
+/// SPIFI data line `N`
+pub trait PinSpifiData<SPIFI, const N: u8> {}
+
+impl PinSpifiSck<SpifiConfig> for P8_2_0<Alternate<2>> {}
+impl PinSpifiCs<SpifiConfig> for P8_2_1<Alternate<2>> {}
+impl PinSpifiData<SpifiConfig, 0> for P8_2_2<Alternate<2>> {}
+impl PinSpifiData<SpifiConfig, 1> for P8_2_3<Alternate<2>> {}
+impl PinSpifiData<SpifiConfig, 2> for P8_2_4<Alternate<2>> {}
+impl PinSpifiData<SpifiConfig, 3> for P8_2_5<Alternate<2>> {}
+
+/// Capture / compare channel `C` of a timer, from 1 to 4
+pub trait PinChannel<TIM, const C: u8> {}
+
+impl PinChannel<Timer32_1, 1> for P16_0_0<Alternate<3>> {}
+impl PinChannel<Timer32_1, 2> for P16_0_1<Alternate<3>> {}
+impl PinChannel<Timer32_1, 3> for P16_0_2<Alternate<3>> {}
+impl PinChannel<Timer32_1, 4> for P16_0_3<Alternate<3>> {}
+
+impl PinChannel<Timer32_2, 1> for P16_1_0<Alternate<3>> {}
+impl PinChannel<Timer32_2, 2> for P16_1_1<Alternate<3>> {}
+impl PinChannel<Timer32_2, 3> for P16_1_2<Alternate<3>> {}
+impl PinChannel<Timer32_2, 4> for P16_1_3<Alternate<3>> {}
+
+/// Timer16 input `I`, 1 (external clock or encoder input 1) or 2 (encoder input 2)
+pub trait PinTimerIn<TIM, const I: u8> {}
+/// Timer16 waveform output
+pub trait PinTimerOut<TIM> {}
+
+impl PinTimerIn<Timer16_0, 1> for P16_0_5<Alternate<3>> {}
+impl PinTimerIn<Timer16_0, 2> for P16_0_6<Alternate<3>> {}
+impl PinTimerOut<Timer16_0> for P16_0_7<Alternate<3>> {}
+
+impl PinTimerIn<Timer16_1, 1> for P16_0_8<Alternate<3>> {}
+impl PinTimerIn<Timer16_1, 2> for P16_0_9<Alternate<3>> {}
+impl PinTimerOut<Timer16_1> for P16_0_10<Alternate<3>> {}
+
+impl PinTimerIn<Timer16_2, 1> for P16_0_11<Alternate<3>> {}
+impl PinTimerIn<Timer16_2, 2> for P16_0_12<Alternate<3>> {}
+impl PinTimerOut<Timer16_2> for P16_0_13<Alternate<3>> {}
+
+/// ADC input
+pub trait PinAdc<ADC> {
+    /// Channel selected in the ADC multiplexer
+    const CHANNEL: u8;
+}
+
+impl PinAdc<Adc> for P16_1_5<Analog> { const CHANNEL: u8 = 0; }
+impl PinAdc<Adc> for P16_1_7<Analog> { const CHANNEL: u8 = 1; }
+impl PinAdc<Adc> for P16_0_2<Analog> { const CHANNEL: u8 = 2; }
+impl PinAdc<Adc> for P16_0_4<Analog> { const CHANNEL: u8 = 3; }
+impl PinAdc<Adc> for P16_0_7<Analog> { const CHANNEL: u8 = 4; }
+impl PinAdc<Adc> for P16_0_9<Analog> { const CHANNEL: u8 = 5; }
+impl PinAdc<Adc> for P16_0_11<Analog> { const CHANNEL: u8 = 6; }
+impl PinAdc<Adc> for P16_0_13<Analog> { const CHANNEL: u8 = 7; }
+
+/// DAC output
+pub trait PinDac<DAC> {}
+
+impl PinDac<Dac0> for P16_1_12<Analog> {}
+impl PinDac<Dac1> for P16_1_13<Analog> {}
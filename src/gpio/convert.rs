@@ -9,25 +9,24 @@ impl<const P: u8, const N: u8, MODE> Pin<P, N, MODE> {
         unsafe {
             (*Gpio::<P>::ptr()).direction_out().write(|w| w.bits(1 << N));
         }
+        self.set_pad_function(0b00);
         Pin::new()
     }
 
+    /// Configures the pin to operate as a push pull output pin with the given drive strength
+    /// Initial state will be low.
+    pub fn into_output_with_drive(mut self, strength: DriveStrength) -> Pin<P, N, Output> {
+        self.set_drive_strength(strength);
+        self.into_output()
+    }
+
     /// Configures the pin to operate as a floating input pin
     pub fn into_floating_input(mut self) -> Pin<P, N, Input<Floating>> {
         unsafe {
             (*Gpio::<P>::ptr()).direction_in().write(|w| w.bits(1 << N));
-            let mask = 0b11 << 2 * N;
-            let value = 0b00 << 2 * N;
-            match P {
-                0 => (*mik32v2_pac::PadConfig::ptr()).pad0_pupd()
-                .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                1 => (*mik32v2_pac::PadConfig::ptr()).pad1_pupd()
-                .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                2 => (*mik32v2_pac::PadConfig::ptr()).pad2_pupd()
-                .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                _ => panic!("Invalid GPIO port number: {}", P)
-            }
-        };
+        }
+        self.set_pull(0b00);
+        self.set_pad_function(0b00);
 
         Pin::new()
     }
@@ -36,18 +35,9 @@ impl<const P: u8, const N: u8, MODE> Pin<P, N, MODE> {
     pub fn into_pull_down_input(mut self) -> Pin<P, N, Input<Floating>> {
         unsafe {
             (*Gpio::<P>::ptr()).direction_in().write(|w| w.bits(1 << N));
-            let mask = 0b11 << 2 * N;
-            let value = 0b10 << 2 * N;
-            match P {
-                0 => (*mik32v2_pac::PadConfig::ptr()).pad0_pupd()
-                .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                1 => (*mik32v2_pac::PadConfig::ptr()).pad1_pupd()
-                .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                2 => (*mik32v2_pac::PadConfig::ptr()).pad2_pupd()
-                .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                _ => panic!("Invalid GPIO port number: {}", P)
-            }
-        };
+        }
+        self.set_pull(0b10);
+        self.set_pad_function(0b00);
 
         Pin::new()
     }
@@ -56,39 +46,65 @@ impl<const P: u8, const N: u8, MODE> Pin<P, N, MODE> {
     pub fn into_pull_up_input(mut self) -> Pin<P, N, Input<Floating>> {
         unsafe {
             (*Gpio::<P>::ptr()).direction_in().write(|w| w.bits(1 << N));
-            let mask = 0b11 << 2 * N;
-            let value = 0b01 << 2 * N;
-            match P {
-                0 => (*mik32v2_pac::PadConfig::ptr()).pad0_pupd()
-                .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                1 => (*mik32v2_pac::PadConfig::ptr()).pad1_pupd()
-                .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                2 => (*mik32v2_pac::PadConfig::ptr()).pad2_pupd()
-                .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                _ => panic!("Invalid GPIO port number: {}", P)
-            }
-        };
+        }
+        self.set_pull(0b01);
+        self.set_pad_function(0b00);
 
         Pin::new()
     }
 
-    /// Configures the output to work in the Func2 Mode
-    pub fn into_serial_port(mut self) -> Pin<P, N, Func2Mode> {
+    /// Configures the pin to operate in the alternate function `F`
+    ///
+    /// `F` is the pad function number of the reference manual, 2 (`Func2_interface`) or
+    /// 3 (`Func3_interface_or_timer`).
+    pub fn into_alternate<const F: u8>(mut self) -> Pin<P, N, Alternate<F>> {
+        const { assert!(F == 2 || F == 3, "the alternate pad functions are 2 and 3") };
+
         unsafe {
             (*Gpio::<P>::ptr()).direction_in().write(|w| w.bits(1 << N));
-            let mask = 0b11 << 2 * N;
-            let value = 0b01 << 2 * N;
+        }
+        self.set_pad_function(F as u32 - 1);
+
+        Pin::new()
+    }
+
+    /// Configures the pin to operate in the pad function 2 used by the serial ports
+    #[deprecated(note = "use `into_alternate::<2>()`")]
+    pub fn into_serial_port(self) -> Pin<P, N, Alternate<2>> {
+        self.into_alternate::<2>()
+    }
+
+    /// Configures the pin to operate as an analog input or output
+    pub fn into_analog(mut self) -> Pin<P, N, Analog> {
+        unsafe {
+            (*Gpio::<P>::ptr()).direction_in().write(|w| w.bits(1 << N));
+        }
+        self.set_pull(0b00);
+        self.set_pad_function(0b11);
+
+        Pin::new()
+    }
+
+    /// Writes the 2 bit pad function of the pin, `0b00` selects the GPIO
+    fn set_pad_function(&mut self, value: u32) {
+        set_pad_function(P, N, value);
+    }
+
+    /// Writes the 2 bit pull resistor selection of the pin
+    fn set_pull(&mut self, value: u32) {
+        let mask = 0b11 << 2 * N;
+        let value = value << 2 * N;
+        // NOTE(unsafe) the pad bits of this pin are owned by the pin
+        unsafe {
             match P {
-                0 => (*mik32v2_pac::PadConfig::ptr()).pad0_cfg()
+                0 => (*mik32v2_pac::PadConfig::ptr()).pad0_pupd()
                 .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                1 => (*mik32v2_pac::PadConfig::ptr()).pad1_cfg()
+                1 => (*mik32v2_pac::PadConfig::ptr()).pad1_pupd()
                 .modify(|r, w| w.bits((r.bits() & !mask) | value)),
-                2 => (*mik32v2_pac::PadConfig::ptr()).pad2_cfg()
+                2 => (*mik32v2_pac::PadConfig::ptr()).pad2_pupd()
                 .modify(|r, w| w.bits((r.bits() & !mask) | value)),
                 _ => panic!("Invalid GPIO port number: {}", P)
             }
         };
-
-        Pin::new()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,176 @@
+use super::*;
+use crate::peripheral::Peripheral;
+
+pub type AnyPin<MODE> = ErasedPin<MODE>;
+
+/// Fully erased pin
+///
+/// - `MODE` is one of the pin modes (see [Modes](crate::gpio#modes) section).
+///
+/// The port and the pin number are kept at runtime, so pins of all ports have the same type.
+pub struct ErasedPin<MODE> {
+    port: u8,
+    i: u8,
+    _mode: PhantomData<MODE>,
+}
+
+impl<MODE> ErasedPin<MODE> {
+    pub(crate) fn new(port: u8, i: u8) -> Self {
+        Self {
+            port,
+            i,
+            _mode: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    fn ptr(&self) -> *const mik32v2_pac::gpio16_0::RegisterBlock {
+        match self.port {
+            0 => mik32v2_pac::Gpio16_0::ptr(),
+            1 => mik32v2_pac::Gpio16_1::ptr() as _,
+            2 => mik32v2_pac::Gpio8_2::ptr() as _,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Sets the output drive strength of the pad
+    pub fn set_drive_strength(&mut self, strength: DriveStrength) {
+        set_drive_strength(self.port, self.i, strength);
+    }
+}
+
+impl<MODE> PinExt for ErasedPin<MODE> {
+    type Mode = MODE;
+
+    #[inline(always)]
+    fn pin_id(&self) -> u8 {
+        self.i
+    }
+    #[inline(always)]
+    fn port_id(&self) -> u8 {
+        self.port
+    }
+}
+
+impl ErasedPin<Output> {
+    #[inline(always)]
+    pub fn set_high(&mut self) {
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe { (*self.ptr()).set().write(|w| w.bits(1 << self.i)); }
+    }
+
+    #[inline(always)]
+    pub fn set_low(&mut self) {
+        // NOTE(unsafe) atomic write to a stateless register
+        unsafe { (*self.ptr()).clear().write(|w| w.bits(1 << self.i)); }
+    }
+
+    #[inline(always)]
+    pub fn get_state(&self) -> PinState {
+        if self.is_set_low() {
+            PinState::Low
+        } else {
+            PinState::High
+        }
+    }
+
+    #[inline(always)]
+    pub fn set_state(&mut self, state: PinState) {
+        match state {
+            PinState::Low => self.set_low(),
+            PinState::High => self.set_high(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_set_high(&self) -> bool {
+        !self.is_set_low()
+    }
+
+    #[inline(always)]
+    pub fn is_set_low(&self) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { (*self.ptr()).state().read().bits() & (1 << self.i) == 0 }
+    }
+
+    #[inline(always)]
+    pub fn toggle(&mut self) {
+        if self.is_set_low() {
+            self.set_high()
+        } else {
+            self.set_low()
+        }
+    }
+}
+
+impl<MODE> ErasedPin<Input<MODE>> {
+    #[inline(always)]
+    pub fn is_high(&self) -> bool {
+        !self.is_low()
+    }
+
+    #[inline(always)]
+    pub fn is_low(&self) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        unsafe { (*self.ptr()).state().read().bits() & (1 << self.i) == 0 }
+    }
+}
+
+impl<const P: u8, const N: u8, MODE> Pin<P, N, MODE> {
+    /// Erases the port and the pin number from the type
+    ///
+    /// This is useful when you want to collect the pins of several ports into an array
+    pub fn erase(self) -> ErasedPin<MODE> {
+        ErasedPin::new(P, N)
+    }
+}
+
+impl<const P: u8, MODE> PartiallyErasedPin<P, MODE> {
+    /// Erases the port from the type
+    pub fn erase(self) -> ErasedPin<MODE> {
+        ErasedPin::new(P, self.pin_id())
+    }
+}
+
+impl<const P: u8, const N: u8, MODE> From<Pin<P, N, MODE>> for ErasedPin<MODE> {
+    fn from(pin: Pin<P, N, MODE>) -> Self {
+        pin.erase()
+    }
+}
+
+impl<const P: u8, MODE> From<PartiallyErasedPin<P, MODE>> for ErasedPin<MODE> {
+    fn from(pin: PartiallyErasedPin<P, MODE>) -> Self {
+        pin.erase()
+    }
+}
+
+/// Fails with the original pin if it is not pin `N` of port `P`
+impl<const P: u8, const N: u8, MODE> TryFrom<ErasedPin<MODE>> for Pin<P, N, MODE> {
+    type Error = ErasedPin<MODE>;
+
+    fn try_from(pin: ErasedPin<MODE>) -> Result<Self, Self::Error> {
+        if pin.port == P && pin.i == N {
+            Ok(Pin::new())
+        } else {
+            Err(pin)
+        }
+    }
+}
+
+impl<MODE> Peripheral for ErasedPin<MODE> {
+    type P = ErasedPin<MODE>;
+
+    #[inline]
+    unsafe fn clone_unchecked(&self) -> Self::P {
+        ErasedPin::new(self.port, self.i)
+    }
+}
+
+impl<const P: u8, const N: u8, MODE> Peripheral for Pin<P, N, MODE> {
+    type P = Pin<P, N, MODE>;
+
+    #[inline]
+    unsafe fn clone_unchecked(&self) -> Self::P {
+        Pin::new()
+    }
+}
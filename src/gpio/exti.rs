@@ -0,0 +1,295 @@
+//! External interrupts through the `GPIO_IRQ` line multiplexers
+//!
+//! `GPIO_IRQ` has 8 interrupt lines, each selecting one pin through its 4 bit field in
+//! `LINE_MUX`. Pin `N` of a port can be routed to line `N % 8` with the mux values 0 to 4
+//! and to line `(N + 4) % 8` with the mux values 5 to 9. All lines share the GPIO line of
+//! the EPIC.
+//!
+//! `LINE_MUX` resets to 0, which selects pins 0 to 7 of port 0 without anyone having routed
+//! them. The HAL therefore keeps its own table of the routes made through
+//! [`ExtiPin::make_interrupt_source`] and never reads the routing back from `LINE_MUX`.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use mik32v2_pac::{GpioIrq, Pm};
+
+use super::{ErasedPin, PartiallyErasedPin, Pin, PinExt, PinState};
+use crate::rcc::Enable;
+
+/// Number of `GPIO_IRQ` lines
+pub const LINES: u8 = 8;
+
+/// The pin cannot be routed to the requested interrupt line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidLine;
+
+/// The pin is not routed to an interrupt line, see [`ExtiPin::make_interrupt_source`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotRouted;
+
+/// Edge of an edge triggered interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    RisingFalling,
+}
+
+/// Interrupt configuration of a pin
+pub trait ExtiPin {
+    /// Returns the two interrupt lines the pin can be routed to
+    fn interrupt_lines(&self) -> [u8; 2];
+    /// Routes the pin to interrupt `line`
+    fn make_interrupt_source(&mut self, line: u8) -> Result<(), InvalidLine>;
+    /// Triggers the interrupt on `edge`
+    ///
+    /// This and the methods below fail with [`NotRouted`] until the pin is routed to a line.
+    fn trigger_on_edge(&mut self, edge: Edge) -> Result<(), NotRouted>;
+    /// Triggers the interrupt while the pin is at `level`
+    fn trigger_on_level(&mut self, level: PinState) -> Result<(), NotRouted>;
+    fn enable_interrupt(&mut self) -> Result<(), NotRouted>;
+    fn disable_interrupt(&mut self) -> Result<(), NotRouted>;
+    fn clear_interrupt_pending_bit(&mut self) -> Result<(), NotRouted>;
+    /// Returns `true` if the line of the pin has a pending interrupt
+    fn check_interrupt(&self) -> bool;
+}
+
+/// Pin routed to each line through `make_interrupt_source`, as `(port, pin)`
+static LINE_OWNERS: [Mutex<Cell<Option<(u8, u8)>>>; LINES as usize] =
+    [const { Mutex::new(Cell::new(None)) }; LINES as usize];
+
+/// Position of pin `i` of `port` among the mux inputs of a line, from 0 to 4
+const fn mux_input(port: u8, i: u8) -> u32 {
+    (port as u32) * 2 + (i >= 8) as u32
+}
+
+pub(super) const fn interrupt_lines(i: u8) -> [u8; 2] {
+    [i % LINES, (i + 4) % LINES]
+}
+
+/// Returns the mux value selecting pin `i` of `port` on `line`
+fn mux_value(port: u8, i: u8, line: u8) -> Option<u32> {
+    let [direct, shifted] = interrupt_lines(i);
+    if line == direct {
+        Some(mux_input(port, i))
+    } else if line == shifted {
+        Some(mux_input(port, i) + 5)
+    } else {
+        None
+    }
+}
+
+/// Returns the line the pin was routed to by `make_interrupt_source`
+pub(super) fn routed_line(port: u8, i: u8) -> Option<u8> {
+    critical_section::with(|cs| {
+        interrupt_lines(i)
+            .into_iter()
+            .find(|&line| LINE_OWNERS[line as usize].borrow(cs).get() == Some((port, i)))
+    })
+}
+
+/// Enables the bus clock of `GPIO_IRQ`, which is gated out of reset
+pub(super) fn enable_clock() {
+    // NOTE(unsafe) atomic write to a stateless register
+    let pm = unsafe { &(*Pm::ptr()) };
+    GpioIrq::enable(pm);
+}
+
+pub(super) fn make_interrupt_source(port: u8, i: u8, line: u8) -> Result<(), InvalidLine> {
+    let value = mux_value(port, i, line).ok_or(InvalidLine)?;
+    enable_clock();
+
+    critical_section::with(|cs| {
+        // NOTE(unsafe) the line mux is shared between pins, modified inside a critical section
+        unsafe {
+            (*GpioIrq::ptr()).line_mux().modify(|r, w| {
+                w.bits((r.bits() & !(0b1111 << (4 * line))) | value << (4 * line))
+            });
+        }
+        // A pin is routed to one line at a time
+        for other in interrupt_lines(i) {
+            let owner = LINE_OWNERS[other as usize].borrow(cs);
+            if owner.get() == Some((port, i)) {
+                owner.set(None);
+            }
+        }
+        LINE_OWNERS[line as usize].borrow(cs).set(Some((port, i)));
+    });
+    Ok(())
+}
+
+/// Applies `f` to the bit of the line the pin is routed to
+fn with_line(
+    port: u8,
+    i: u8,
+    f: impl FnOnce(&mik32v2_pac::gpio_irq::RegisterBlock, u32),
+) -> Result<(), NotRouted> {
+    let line = routed_line(port, i).ok_or(NotRouted)?;
+    enable_clock();
+    // NOTE(unsafe) the set / clear registers only change the bits written as 1
+    f(unsafe { &*GpioIrq::ptr() }, 1 << line);
+    Ok(())
+}
+
+pub(super) fn trigger_on_edge(port: u8, i: u8, edge: Edge) -> Result<(), NotRouted> {
+    with_line(port, i, |irq, bit| unsafe {
+        irq.edge().write(|w| w.bits(bit));
+        match edge {
+            Edge::Rising => {
+                irq.any_edge_clear().write(|w| w.bits(bit));
+                irq.level_set().write(|w| w.bits(bit));
+            }
+            Edge::Falling => {
+                irq.any_edge_clear().write(|w| w.bits(bit));
+                irq.level_clear().write(|w| w.bits(bit));
+            }
+            Edge::RisingFalling => {
+                irq.any_edge_set().write(|w| w.bits(bit));
+            }
+        }
+    })
+}
+
+pub(super) fn trigger_on_level(port: u8, i: u8, level: PinState) -> Result<(), NotRouted> {
+    with_line(port, i, |irq, bit| unsafe {
+        irq.level().write(|w| w.bits(bit));
+        irq.any_edge_clear().write(|w| w.bits(bit));
+        match level {
+            PinState::High => {
+                irq.level_set().write(|w| w.bits(bit));
+            }
+            PinState::Low => {
+                irq.level_clear().write(|w| w.bits(bit));
+            }
+        }
+    })
+}
+
+fn enable_interrupt(port: u8, i: u8) -> Result<(), NotRouted> {
+    with_line(port, i, |irq, bit| unsafe {
+        irq.enable_set().write(|w| w.bits(bit));
+    })
+}
+
+fn disable_interrupt(port: u8, i: u8) -> Result<(), NotRouted> {
+    with_line(port, i, |irq, bit| unsafe {
+        irq.enable_clear().write(|w| w.bits(bit));
+    })
+}
+
+fn clear_interrupt_pending_bit(port: u8, i: u8) -> Result<(), NotRouted> {
+    with_line(port, i, |irq, bit| unsafe {
+        irq.clear().write(|w| w.bits(bit));
+    })
+}
+
+fn check_interrupt(port: u8, i: u8) -> bool {
+    match routed_line(port, i) {
+        // NOTE(unsafe) atomic read with no side effects
+        Some(line) => unsafe { (*GpioIrq::ptr()).interrupt().read().bits() & (1 << line) != 0 },
+        None => false,
+    }
+}
+
+impl<const P: u8, const N: u8, MODE> ExtiPin for Pin<P, N, MODE> {
+    fn interrupt_lines(&self) -> [u8; 2] {
+        interrupt_lines(N)
+    }
+
+    fn make_interrupt_source(&mut self, line: u8) -> Result<(), InvalidLine> {
+        make_interrupt_source(P, N, line)
+    }
+
+    fn trigger_on_edge(&mut self, edge: Edge) -> Result<(), NotRouted> {
+        trigger_on_edge(P, N, edge)
+    }
+
+    fn trigger_on_level(&mut self, level: PinState) -> Result<(), NotRouted> {
+        trigger_on_level(P, N, level)
+    }
+
+    fn enable_interrupt(&mut self) -> Result<(), NotRouted> {
+        enable_interrupt(P, N)
+    }
+
+    fn disable_interrupt(&mut self) -> Result<(), NotRouted> {
+        disable_interrupt(P, N)
+    }
+
+    fn clear_interrupt_pending_bit(&mut self) -> Result<(), NotRouted> {
+        clear_interrupt_pending_bit(P, N)
+    }
+
+    fn check_interrupt(&self) -> bool {
+        check_interrupt(P, N)
+    }
+}
+
+impl<const P: u8, MODE> ExtiPin for PartiallyErasedPin<P, MODE> {
+    fn interrupt_lines(&self) -> [u8; 2] {
+        interrupt_lines(self.pin_id())
+    }
+
+    fn make_interrupt_source(&mut self, line: u8) -> Result<(), InvalidLine> {
+        make_interrupt_source(P, self.pin_id(), line)
+    }
+
+    fn trigger_on_edge(&mut self, edge: Edge) -> Result<(), NotRouted> {
+        trigger_on_edge(P, self.pin_id(), edge)
+    }
+
+    fn trigger_on_level(&mut self, level: PinState) -> Result<(), NotRouted> {
+        trigger_on_level(P, self.pin_id(), level)
+    }
+
+    fn enable_interrupt(&mut self) -> Result<(), NotRouted> {
+        enable_interrupt(P, self.pin_id())
+    }
+
+    fn disable_interrupt(&mut self) -> Result<(), NotRouted> {
+        disable_interrupt(P, self.pin_id())
+    }
+
+    fn clear_interrupt_pending_bit(&mut self) -> Result<(), NotRouted> {
+        clear_interrupt_pending_bit(P, self.pin_id())
+    }
+
+    fn check_interrupt(&self) -> bool {
+        check_interrupt(P, self.pin_id())
+    }
+}
+
+impl<MODE> ExtiPin for ErasedPin<MODE> {
+    fn interrupt_lines(&self) -> [u8; 2] {
+        interrupt_lines(self.pin_id())
+    }
+
+    fn make_interrupt_source(&mut self, line: u8) -> Result<(), InvalidLine> {
+        make_interrupt_source(self.port_id(), self.pin_id(), line)
+    }
+
+    fn trigger_on_edge(&mut self, edge: Edge) -> Result<(), NotRouted> {
+        trigger_on_edge(self.port_id(), self.pin_id(), edge)
+    }
+
+    fn trigger_on_level(&mut self, level: PinState) -> Result<(), NotRouted> {
+        trigger_on_level(self.port_id(), self.pin_id(), level)
+    }
+
+    fn enable_interrupt(&mut self) -> Result<(), NotRouted> {
+        enable_interrupt(self.port_id(), self.pin_id())
+    }
+
+    fn disable_interrupt(&mut self) -> Result<(), NotRouted> {
+        disable_interrupt(self.port_id(), self.pin_id())
+    }
+
+    fn clear_interrupt_pending_bit(&mut self) -> Result<(), NotRouted> {
+        clear_interrupt_pending_bit(self.port_id(), self.pin_id())
+    }
+
+    fn check_interrupt(&self) -> bool {
+        check_interrupt(self.port_id(), self.pin_id())
+    }
+}
@@ -22,6 +22,32 @@ impl<const P: u8, MODE> PartiallyErasedPin<P, MODE> {
     }
 }
 
+impl<const P: u8, MODE> PartiallyErasedPin<P, MODE> {
+    /// Sets the output drive strength of the pad
+    pub fn set_drive_strength(&mut self, strength: DriveStrength) {
+        set_drive_strength(P, self.i, strength);
+    }
+
+    /// Configures the pin to operate as an push pull output pin
+    /// Initial state will be low.
+    pub fn into_output(self) -> PartiallyErasedPin<P, Output> {
+        // NOTE(unsafe) atomic writes to stateless registers
+        unsafe {
+            (*Gpio::<P>::ptr()).clear().write(|w| w.bits(1 << self.i));
+            (*Gpio::<P>::ptr()).direction_out().write(|w| w.bits(1 << self.i));
+        }
+        set_pad_function(P, self.i, 0b00);
+        PartiallyErasedPin::new(self.i)
+    }
+
+    /// Configures the pin to operate as a push pull output pin with the given drive strength
+    /// Initial state will be low.
+    pub fn into_output_with_drive(mut self, strength: DriveStrength) -> PartiallyErasedPin<P, Output> {
+        self.set_drive_strength(strength);
+        self.into_output()
+    }
+}
+
 impl<const P: u8, MODE> PinExt for PartiallyErasedPin<P, MODE> {
     type Mode = MODE;
 
@@ -45,11 +71,7 @@ impl<const P: u8> PartiallyErasedPin<P, Output> {
     #[inline(always)]
     pub fn set_low(&mut self) {
         // NOTE(unsafe) atomic write to a stateless register
-        unsafe {
-            (*Gpio::<P>::ptr())
-                .clear()
-                .write(|w| w.bits(1 << (self.i + 16)));
-        }
+        unsafe { (*Gpio::<P>::ptr()).clear().write(|w| w.bits(1 << self.i)); }
     }
 
     #[inline(always)]
@@ -0,0 +1,279 @@
+//! Async waiting on GPIO inputs
+//!
+//! A waiting pin uses the `GPIO_IRQ` line it is routed to, or routes itself to the first
+//! free one of its two lines if it is not routed yet. A line is free while its interrupt is
+//! disabled and no task waits on it. If both lines are taken, the wait stays pending until
+//! another wait releases a line. Lines held through [`ExtiPin`](super::ExtiPin) do not wake
+//! it when they are released. [`on_interrupt`] masks the lines that fired and wakes their
+//! tasks, it must be called from the trap handler whenever the EPIC reports the GPIO line.
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use critical_section::{CriticalSection, Mutex};
+use embedded_hal_async::digital::Wait;
+use mik32v2_pac::{Epic, GpioIrq, Pm};
+
+use super::exti::{self, Edge, LINES};
+use crate::rcc::Enable;
+use super::{ErasedPin, Input, PartiallyErasedPin, Pin, PinExt, PinState};
+
+static WAKERS: [Mutex<RefCell<Option<Waker>>>; LINES as usize] =
+    [const { Mutex::new(RefCell::new(None)) }; LINES as usize];
+
+/// Number of tasks that can wait for a line at the same time. When more register, all of
+/// them are woken and register again on their next poll.
+const FREED_WAKERS: usize = 4;
+
+/// Tasks whose pin found both of its lines taken
+static LINE_FREED: Mutex<RefCell<[Option<Waker>; FREED_WAKERS]>> =
+    Mutex::new(RefCell::new([const { None }; FREED_WAKERS]));
+
+/// Handles the GPIO interrupt, waking the tasks waiting on the lines that fired
+pub fn on_interrupt() {
+    // NOTE(unsafe) only called from the interrupt handler
+    let irq = unsafe { &*GpioIrq::ptr() };
+    let pending = irq.interrupt().read().bits() & irq.enable_set().read().bits();
+
+    for line in 0..LINES {
+        let bit = 1 << line;
+        if pending & bit == 0 {
+            continue;
+        }
+        // NOTE(unsafe) the set / clear registers only change the bits written as 1
+        unsafe {
+            irq.enable_clear().write(|w| w.bits(bit));
+            irq.clear().write(|w| w.bits(bit));
+        }
+        // The waker stays registered, so the line is not handed to another pin before the
+        // waiting task has seen it fire
+        critical_section::with(|cs| {
+            if let Some(waker) = WAKERS[line as usize].borrow_ref(cs).as_ref() {
+                waker.wake_by_ref();
+            }
+        });
+    }
+
+    // NOTE(unsafe) atomic write to a stateless register
+    unsafe { (*Epic::ptr()).clear().write(|w| w.gpio().set_bit()) };
+}
+
+/// Condition a pin is waited for
+#[derive(Clone, Copy)]
+enum Trigger {
+    Edge(Edge),
+    Level(PinState),
+}
+
+/// Returns `true` if the interrupt of `line` is disabled and no task waits on it
+fn line_is_free(cs: CriticalSection, line: u8) -> bool {
+    // NOTE(unsafe) atomic read with no side effects
+    let enabled = unsafe { (*GpioIrq::ptr()).enable_set().read().bits() } & (1 << line) != 0;
+    !enabled && WAKERS[line as usize].borrow_ref(cs).is_none()
+}
+
+/// Returns the line of pin `i` of `port`, routing the pin to a free line if needed
+///
+/// Returns `None` if the pin is not routed yet and both of its lines are in use.
+fn claim_line(cs: CriticalSection, port: u8, i: u8) -> Option<u8> {
+    if let Some(line) = exti::routed_line(port, i) {
+        return Some(line);
+    }
+
+    let line = exti::interrupt_lines(i)
+        .into_iter()
+        .find(|&line| line_is_free(cs, line))?;
+    exti::make_interrupt_source(port, i, line)
+        .expect("`interrupt_lines` only returns valid lines");
+    Some(line)
+}
+
+/// Registers `waker` to be woken when a waiting future releases its line
+fn wait_line_freed(cs: CriticalSection, waker: &Waker) {
+    let mut wakers = LINE_FREED.borrow_ref_mut(cs);
+    if wakers.iter().flatten().any(|registered| registered.will_wake(waker)) {
+        return;
+    }
+    if wakers.iter().all(Option::is_some) {
+        wake_all(&mut wakers);
+    }
+    if let Some(slot) = wakers.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(waker.clone());
+    }
+}
+
+/// Wakes and unregisters every task in `wakers`
+fn wake_all(wakers: &mut [Option<Waker>; FREED_WAKERS]) {
+    for waker in wakers.iter_mut().filter_map(Option::take) {
+        waker.wake();
+    }
+}
+
+/// Line claimed by a waiting future
+///
+/// Dropping it masks the line and unregisters the waker, so a cancelled wait releases the
+/// line.
+struct LineGuard {
+    port: u8,
+    i: u8,
+    line: Option<u8>,
+}
+
+impl Drop for LineGuard {
+    fn drop(&mut self) {
+        let Some(line) = self.line else {
+            return;
+        };
+        critical_section::with(|cs| {
+            // Leave the line alone if it was routed to another pin through `ExtiPin`
+            if exti::routed_line(self.port, self.i) == Some(line) {
+                // NOTE(unsafe) the set / clear registers only change the bits written as 1
+                unsafe { (*GpioIrq::ptr()).enable_clear().write(|w| w.bits(1 << line)) };
+            }
+            WAKERS[line as usize].borrow_ref_mut(cs).take();
+            wake_all(&mut LINE_FREED.borrow_ref_mut(cs));
+        });
+    }
+}
+
+/// Waits until the line of pin `i` of `port` fires on `trigger`
+async fn wait_for(port: u8, i: u8, trigger: Trigger) {
+    // NOTE(unsafe) the set / clear registers only change the bits written as 1
+    let irq = unsafe { &*GpioIrq::ptr() };
+    let mut guard = LineGuard { port, i, line: None };
+
+    poll_fn(|cx| {
+        critical_section::with(|cs| {
+            let Some(line) = guard.line else {
+                // `GPIO_IRQ` is gated out of reset, its registers read as 0 until enabled
+                exti::enable_clock();
+                let Some(line) = claim_line(cs, port, i) else {
+                    wait_line_freed(cs, cx.waker());
+                    return Poll::Pending;
+                };
+                let bit = 1 << line;
+
+                match trigger {
+                    Trigger::Edge(edge) => exti::trigger_on_edge(port, i, edge),
+                    Trigger::Level(level) => exti::trigger_on_level(port, i, level),
+                }
+                .expect("the pin was routed to `line` above");
+
+                *WAKERS[line as usize].borrow_ref_mut(cs) = Some(cx.waker().clone());
+                guard.line = Some(line);
+                // NOTE(unsafe) atomic writes to stateless registers
+                unsafe {
+                    irq.clear().write(|w| w.bits(bit));
+                    irq.enable_set().write(|w| w.bits(bit));
+                    Epic::enable(&*Pm::ptr());
+                    (*Epic::ptr()).mask_level_set().write(|w| w.gpio().set_bit());
+                }
+                return Poll::Pending;
+            };
+
+            // The interrupt handler masks the line once it fired
+            if irq.enable_set().read().bits() & (1 << line) == 0 {
+                Poll::Ready(())
+            } else {
+                *WAKERS[line as usize].borrow_ref_mut(cs) = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    })
+    .await
+}
+
+impl<const P: u8, const N: u8, MODE> Wait for Pin<P, N, Input<MODE>> {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        if !self.is_high() {
+            wait_for(P, N, Trigger::Level(PinState::High)).await;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        if !self.is_low() {
+            wait_for(P, N, Trigger::Level(PinState::Low)).await;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        wait_for(P, N, Trigger::Edge(Edge::Rising)).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        wait_for(P, N, Trigger::Edge(Edge::Falling)).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        wait_for(P, N, Trigger::Edge(Edge::RisingFalling)).await;
+        Ok(())
+    }
+}
+
+impl<const P: u8, MODE> Wait for PartiallyErasedPin<P, Input<MODE>> {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        if !self.is_high() {
+            wait_for(P, self.pin_id(), Trigger::Level(PinState::High)).await;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        if !self.is_low() {
+            wait_for(P, self.pin_id(), Trigger::Level(PinState::Low)).await;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        wait_for(P, self.pin_id(), Trigger::Edge(Edge::Rising)).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        wait_for(P, self.pin_id(), Trigger::Edge(Edge::Falling)).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        wait_for(P, self.pin_id(), Trigger::Edge(Edge::RisingFalling)).await;
+        Ok(())
+    }
+}
+
+impl<MODE> Wait for ErasedPin<Input<MODE>> {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        if !self.is_high() {
+            wait_for(self.port_id(), self.pin_id(), Trigger::Level(PinState::High)).await;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        if !self.is_low() {
+            wait_for(self.port_id(), self.pin_id(), Trigger::Level(PinState::Low)).await;
+        }
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        wait_for(self.port_id(), self.pin_id(), Trigger::Edge(Edge::Rising)).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        wait_for(self.port_id(), self.pin_id(), Trigger::Edge(Edge::Falling)).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        wait_for(self.port_id(), self.pin_id(), Trigger::Edge(Edge::RisingFalling)).await;
+        Ok(())
+    }
+}
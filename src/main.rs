@@ -1,12 +1,14 @@
-#![no_std]
-#![no_main]
-#![feature(riscv_ext_intrinsics)]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(not(test), feature(riscv_ext_intrinsics))]
 
-use core::{arch::riscv32::nop, fmt::Write, mem::take, panic::PanicInfo};
+use core::{fmt::Write, mem::take, panic::PanicInfo};
 use embedded_hal_nb::serial::{Read, Write as NbWrite};
 use gpio::{GpioExt, Input, PinExt};
 use mik32v2_pac::{epic::mask_edge_clear::Gpio, pm::ahb_mux::AhbClkMux, spi_0::delay, Peripherals};
+#[cfg(not(test))]
 use mik32_rt::entry;
+mod dma;
 mod rcc;
 mod time;
 mod serial;
@@ -19,7 +21,8 @@ use serial::Serial;
 
 #[derive(Debug)]
 enum Error {
-    PeripheralsAlreadyTaken
+    PeripheralsAlreadyTaken,
+    Rcc(rcc::Error),
 }
 
 struct Config {
@@ -32,39 +35,41 @@ impl Default for Config {
     }
 }
 
-fn init(config: Config) -> Result<Peripherals, Error> {
+fn init(config: Config) -> Result<(Peripherals, rcc::Clocks), Error> {
     let mut p = Peripherals::take();
     if p.is_none() {
         return Err(Error::PeripheralsAlreadyTaken);
     }
     let p = p.unwrap();
 
-    rcc::Config::init(config.rcc);
+    let clocks = rcc::Config::init(config.rcc).map_err(Error::Rcc)?;
 
 
-    Ok(p)
+    Ok((p, clocks))
 }
 
+#[cfg(not(test))]
 #[entry]
 fn main() -> ! {
     let mut device_config = Config::default();
 
-    let p = init(device_config).unwrap();
+    let (p, clocks) = init(device_config).unwrap();
 
     let gpio_2 = p.gpio8_2.split();
     let gpio_0 = p.gpio16_0.split();
     let mut led = gpio_2.p8_2_7.into_output();
 
-    let mut tx = gpio_0.p16_0_6.into_serial_port();
-    let mut rx = gpio_0.p16_0_5.into_serial_port();
+    let mut tx = gpio_0.p16_0_6.into_alternate::<2>();
+    let mut rx = gpio_0.p16_0_5.into_alternate::<2>();
 
     let pins = (tx, rx);
 
     let serial = Serial::new(
         p.usart_0,
         pins,
-        serial::Config {  }
-    );
+        serial::Config::default().baudrate(time::bps(9600)),
+        &clocks,
+    ).unwrap();
 
     let (mut tx, mut rx) = serial.split();
     writeln!(tx, "Hello, world!").unwrap();
@@ -76,6 +81,7 @@ fn main() -> ! {
 }
 
 
+#[cfg(not(test))]
 #[unsafe(export_name = "trap_handler")]
 fn trap() {
     loop {
@@ -83,6 +89,7 @@ fn trap() {
     }
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     loop {}
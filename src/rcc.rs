@@ -2,19 +2,60 @@ use mik32v2_pac::generic::Resettable;
 use mik32v2_pac::pm::ahb_mux::AhbClkMux;
 use mik32v2_pac::wake_up::clocks_bu::{self, RtcClkMux};
 use mik32v2_pac::pm::cpu_rtc_clk_mux::CpuRtcClkMux;
+use mik32v2_pac::pm::wdt_clk_mux::WdtClkMux;
 use mik32v2_pac::wake_up::clocks_sys::Force32kClk;
 use mik32v2_pac::pm::ahb_mux::ForceMux;
 use mik32v2_pac::pm::DivAhb;
-use mik32v2_pac::{Pm, WakeUp};
+use mik32v2_pac::{
+    Adc, Crc, Crypto, Dac0, Dac1, Dma, EepromRegs, Epic, Gpio16_0, Gpio16_1, Gpio8_2, GpioIrq, I2c0, I2c1, Otp,
+    Pm, PvdVcc, Rtc, Spi0, Spi1, SpifiConfig, Timer16_0, Timer16_1, Timer16_2, Timer32_0,
+    Timer32_1, Timer32_2, Usart0, Usart1, WakeUp, Wdt, WdtBus,
+};
+use core::cell::Cell;
+use critical_section::Mutex;
 use crate::time::Hertz;
 
+mod calibration;
+pub use calibration::{calibrate_hsi32m, calibrate_lsi32k, CalibrationReference};
+
 const CLOCKSWITCH_TIMEOUT_VALUE: u32 = 500_000;
 
+/// `CLOCKS_SYS` fields written by `init`: `FORCE_32K_CLK` and `ADJ_HSI32M`
+const CLOCKS_SYS_CONFIG_MASK: u32 = 0b1111_1111_1100;
+/// `CLOCKS_BU` fields written by `init`: `RTC_CLK_MUX` and `ADJ_LSI32K`
+const CLOCKS_BU_CONFIG_MASK: u32 = 0b1111_1100_0000;
+
+/// Largest division factor of the AHB, APB_M and APB_P dividers
+pub const MAX_BUS_DIV: u16 = 256;
+/// Largest value of the 4 bit `ADJ_LSI32K` field
+pub const MAX_LSI32K_CALIBRATION: u8 = 0b1111;
+
 pub const HSI32M_FREQ: Hertz = Hertz(32_000_000);
 pub const OSC32M_FREQ: Hertz = Hertz(32_000_000);
 pub const LSI32K_FREQ: Hertz = Hertz(32_000);
 pub const OSC32K_FREQ: Hertz = Hertz(32_000);
 
+/// Clock configuration error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The external 32 MHz oscillator did not start
+    Osc32mNotReady,
+    /// The internal 32 MHz RC oscillator did not start
+    Hsi32mNotReady,
+    /// The external 32 kHz oscillator did not start
+    Osc32kNotReady,
+    /// The internal 32 kHz RC oscillator did not start
+    Lsi32kNotReady,
+    /// A bus division factor is outside of `1..=MAX_BUS_DIV`
+    InvalidDivider,
+    /// The LSI32K calibration value does not fit into `ADJ_LSI32K`
+    InvalidCalibration,
+    /// A clock is selected from an oscillator that the configuration disables
+    SourceDisabled,
+    /// A calibration timer did not return two equal consecutive counter reads
+    UnstableCounter,
+}
+
 pub struct FreqMonitir {
     pub sys: AhbClkMux,
     pub force_osc_sys: ForceMux,
@@ -31,6 +72,56 @@ impl Default for FreqMonitir {
     }
 }
 
+/// A `Timer32_x` instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Timer32Id {
+    Timer32_0 = 0,
+    Timer32_1 = 1,
+    Timer32_2 = 2,
+}
+
+/// A `Timer16_x` instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Timer16Id {
+    Timer16_0 = 0,
+    Timer16_1 = 1,
+    Timer16_2 = 2,
+}
+
+/// Synchronous clock of the TIM1 input of a `Timer32_x`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Timer32SyncClk {
+    /// System clock before the AHB divider
+    SysClk = 0,
+    /// AHB clock
+    Hclk = 1,
+}
+
+/// Asynchronous clock of the TIM2 input of a `Timer32_x`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Timer32AsyncClk {
+    Osc32k = 0,
+    Lsi32k = 1,
+}
+
+/// Clock of a `Timer16_x`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Timer16Clk {
+    /// System clock before the AHB divider
+    SysClk = 0,
+    /// AHB clock
+    Hclk = 1,
+    Osc32m = 2,
+    Hsi32m = 3,
+    Osc32k = 4,
+    Lsi32k = 5,
+}
+
 pub struct Config {
     pub hsi32m: bool,
     pub osc32m: bool,
@@ -38,16 +129,26 @@ pub struct Config {
     pub osc32k: bool,
     pub freq_monitor: FreqMonitir,
 
-    pub ahb_div: u8,
-    pub apb_m_div: u8,
-    pub apb_p_div: u8,
+    /// AHB division factor, `1..=MAX_BUS_DIV`
+    pub ahb_divider: u16,
+    /// APB_M division factor, `1..=MAX_BUS_DIV`
+    pub apb_m_divider: u16,
+    /// APB_P division factor, `1..=MAX_BUS_DIV`
+    pub apb_p_divider: u16,
 
     pub hsi32m_calibration_value: u8,
     pub lsi32k_calibration_value: u8,
 
     pub rtcclk: RtcClkMux,
     pub rtccpuclk: CpuRtcClkMux,
-    
+
+    pub wdtclk: WdtClkMux,
+    /// TIM1 input clocks of `Timer32_0..=2`
+    pub timer32_sync: [Timer32SyncClk; 3],
+    /// TIM2 input clocks of `Timer32_0..=2`
+    pub timer32_async: [Timer32AsyncClk; 3],
+    /// Clocks of `Timer16_0..=2`
+    pub timer16: [Timer16Clk; 3],
 }
 
 impl Default for Config {
@@ -58,19 +159,328 @@ impl Default for Config {
             lsi32k: true,
             osc32k: true,
             freq_monitor: FreqMonitir::default(),
-            ahb_div: 0,
-            apb_m_div: 0,
-            apb_p_div: 0,
+            ahb_divider: 1,
+            apb_m_divider: 1,
+            apb_p_divider: 1,
             hsi32m_calibration_value: 128,
             lsi32k_calibration_value: 8,
             rtcclk: RtcClkMux::Automatic,
             rtccpuclk: CpuRtcClkMux::Osc32k,
+            wdtclk: WdtClkMux::Osc32m,
+            timer32_sync: [Timer32SyncClk::SysClk; 3],
+            timer32_async: [Timer32AsyncClk::Osc32k; 3],
+            timer16: [Timer16Clk::SysClk; 3],
         }
     }
 }
 
+/// Raw values `Config::init` writes to the PM and WU registers
+///
+/// The oscillator enable bits are not included, `init` sets them around the switch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RegisterValues {
+    ahb_mux: u32,
+    div_ahb: u32,
+    div_apb_m: u32,
+    div_apb_p: u32,
+    cpu_rtc_clk_mux: u32,
+    wdt_clk_mux: u32,
+    timer_cfg: u32,
+    /// `CLOCKS_SYS` bits under `CLOCKS_SYS_CONFIG_MASK`
+    clocks_sys: u32,
+    /// `CLOCKS_BU` bits under `CLOCKS_BU_CONFIG_MASK`
+    clocks_bu: u32,
+}
+
+/// Frozen clock frequencies
+///
+/// The existence of this value indicates that the clock configuration can no longer be changed
+#[derive(Clone, Copy, Debug)]
+pub struct Clocks {
+    sysclk: Hertz,
+    ahb: Hertz,
+    apb_m: Hertz,
+    apb_p: Hertz,
+    wdt: Hertz,
+    timer32_sync: [Hertz; 3],
+    timer32_async: [Hertz; 3],
+    timer16: [Hertz; 3],
+}
+
+impl Clocks {
+    /// Returns the system (core) frequency
+    pub const fn sysclk(&self) -> Hertz {
+        self.sysclk
+    }
+
+    /// Returns the frequency of the AHB bus
+    pub const fn ahb(&self) -> Hertz {
+        self.ahb
+    }
+
+    /// Returns the frequency of the APB_M bus
+    pub const fn apb_m(&self) -> Hertz {
+        self.apb_m
+    }
+
+    /// Returns the frequency of the APB_P bus
+    pub const fn apb_p(&self) -> Hertz {
+        self.apb_p
+    }
+
+    /// Returns the watchdog clock frequency
+    pub const fn wdt(&self) -> Hertz {
+        self.wdt
+    }
+
+    /// Returns the frequency of the TIM1 input of `timer`
+    pub const fn timer32_sync(&self, timer: Timer32Id) -> Hertz {
+        self.timer32_sync[timer as usize]
+    }
+
+    /// Returns the frequency of the TIM2 input of `timer`
+    pub const fn timer32_async(&self, timer: Timer32Id) -> Hertz {
+        self.timer32_async[timer as usize]
+    }
+
+    /// Returns the clock frequency of `timer`
+    pub const fn timer16(&self, timer: Timer16Id) -> Hertz {
+        self.timer16[timer as usize]
+    }
+}
+
 impl Config {
-    pub fn init(config: Config) {
+    /// Selects the system clock source
+    pub fn sysclk_source(mut self, source: AhbClkMux) -> Self {
+        self.freq_monitor.sys = source;
+        self
+    }
+
+    /// Sets the AHB division factor, `1..=MAX_BUS_DIV`
+    pub fn ahb_div(mut self, div: u16) -> Self {
+        self.ahb_divider = div;
+        self
+    }
+
+    /// Sets the APB_M division factor, `1..=MAX_BUS_DIV`
+    pub fn apb_m_div(mut self, div: u16) -> Self {
+        self.apb_m_divider = div;
+        self
+    }
+
+    /// Sets the APB_P division factor, `1..=MAX_BUS_DIV`
+    pub fn apb_p_div(mut self, div: u16) -> Self {
+        self.apb_p_divider = div;
+        self
+    }
+
+    /// Sets the HSI32M trimming value
+    pub fn hsi32m_calibration(mut self, value: u8) -> Self {
+        self.hsi32m_calibration_value = value;
+        self
+    }
+
+    /// Sets the LSI32K trimming value, `0..=MAX_LSI32K_CALIBRATION`
+    pub fn lsi32k_calibration(mut self, value: u8) -> Self {
+        self.lsi32k_calibration_value = value;
+        self
+    }
+
+    /// Selects the RTC clock source
+    pub fn rtc_clock(mut self, source: RtcClkMux) -> Self {
+        self.rtcclk = source;
+        self
+    }
+
+    /// Selects the clock source of the core RTC (system timer)
+    pub fn cpu_rtc_clock(mut self, source: CpuRtcClkMux) -> Self {
+        self.rtccpuclk = source;
+        self
+    }
+
+    /// Selects the watchdog clock source
+    pub fn wdt_clock(mut self, source: WdtClkMux) -> Self {
+        self.wdtclk = source;
+        self
+    }
+
+    /// Selects the TIM1 and TIM2 input clocks of `timer`
+    pub fn timer32_clocks(mut self, timer: Timer32Id, sync: Timer32SyncClk, asynchronous: Timer32AsyncClk) -> Self {
+        self.timer32_sync[timer as usize] = sync;
+        self.timer32_async[timer as usize] = asynchronous;
+        self
+    }
+
+    /// Selects the clock of `timer`
+    pub fn timer16_clock(mut self, timer: Timer16Id, source: Timer16Clk) -> Self {
+        self.timer16[timer as usize] = source;
+        self
+    }
+
+    /// Checks the configuration and calculates the resulting clock frequencies
+    ///
+    /// This does not touch the hardware.
+    pub fn clocks(&self) -> Result<Clocks, Error> {
+        let valid_div = 1..=MAX_BUS_DIV;
+        if !valid_div.contains(&self.ahb_divider)
+            || !valid_div.contains(&self.apb_m_divider)
+            || !valid_div.contains(&self.apb_p_divider)
+        {
+            return Err(Error::InvalidDivider);
+        }
+        if self.lsi32k_calibration_value > MAX_LSI32K_CALIBRATION {
+            return Err(Error::InvalidCalibration);
+        }
+        self.check_sources()?;
+
+        let sysclk = match self.freq_monitor.sys {
+            AhbClkMux::Osc32m => OSC32M_FREQ,
+            AhbClkMux::Hsi32m => HSI32M_FREQ,
+            AhbClkMux::Osc32k => OSC32K_FREQ,
+            AhbClkMux::Lsi32k => LSI32K_FREQ,
+        };
+        let ahb = sysclk / self.ahb_divider;
+
+        let wdt = match self.wdtclk {
+            WdtClkMux::Osc32m => OSC32M_FREQ,
+            WdtClkMux::Hsi32m => HSI32M_FREQ,
+            WdtClkMux::Osc32k => OSC32K_FREQ,
+            WdtClkMux::Lsi32k => LSI32K_FREQ,
+        };
+        let timer32_sync = self.timer32_sync.map(|clk| match clk {
+            Timer32SyncClk::SysClk => sysclk,
+            Timer32SyncClk::Hclk => ahb,
+        });
+        let timer32_async = self.timer32_async.map(|clk| match clk {
+            Timer32AsyncClk::Osc32k => OSC32K_FREQ,
+            Timer32AsyncClk::Lsi32k => LSI32K_FREQ,
+        });
+        let timer16 = self.timer16.map(|clk| match clk {
+            Timer16Clk::SysClk => sysclk,
+            Timer16Clk::Hclk => ahb,
+            Timer16Clk::Osc32m => OSC32M_FREQ,
+            Timer16Clk::Hsi32m => HSI32M_FREQ,
+            Timer16Clk::Osc32k => OSC32K_FREQ,
+            Timer16Clk::Lsi32k => LSI32K_FREQ,
+        });
+
+        Ok(Clocks {
+            sysclk,
+            ahb,
+            apb_m: ahb / self.apb_m_divider,
+            apb_p: ahb / self.apb_p_divider,
+            wdt,
+            timer32_sync,
+            timer32_async,
+            timer16,
+        })
+    }
+
+    /// Returns `true` if the oscillator `source` stays enabled after `init`
+    fn is_enabled(&self, source: AhbClkMux) -> bool {
+        match source {
+            AhbClkMux::Osc32m => self.osc32m,
+            AhbClkMux::Hsi32m => self.hsi32m,
+            AhbClkMux::Osc32k => self.osc32k,
+            AhbClkMux::Lsi32k => self.lsi32k,
+        }
+    }
+
+    /// Checks that no clock is selected from an oscillator disabled by this configuration
+    fn check_sources(&self) -> Result<(), Error> {
+        let wdt = match self.wdtclk {
+            WdtClkMux::Osc32m => AhbClkMux::Osc32m,
+            WdtClkMux::Hsi32m => AhbClkMux::Hsi32m,
+            WdtClkMux::Osc32k => AhbClkMux::Osc32k,
+            WdtClkMux::Lsi32k => AhbClkMux::Lsi32k,
+        };
+        let cpu_rtc = match self.rtccpuclk {
+            CpuRtcClkMux::Osc32k => AhbClkMux::Osc32k,
+            CpuRtcClkMux::Lsi32k => AhbClkMux::Lsi32k,
+        };
+        let timer32_async = self.timer32_async.iter().map(|clk| match clk {
+            Timer32AsyncClk::Osc32k => AhbClkMux::Osc32k,
+            Timer32AsyncClk::Lsi32k => AhbClkMux::Lsi32k,
+        });
+        // The system clock based timer sources are covered by the system clock itself
+        let timer16 = self.timer16.iter().filter_map(|clk| match clk {
+            Timer16Clk::SysClk | Timer16Clk::Hclk => None,
+            Timer16Clk::Osc32m => Some(AhbClkMux::Osc32m),
+            Timer16Clk::Hsi32m => Some(AhbClkMux::Hsi32m),
+            Timer16Clk::Osc32k => Some(AhbClkMux::Osc32k),
+            Timer16Clk::Lsi32k => Some(AhbClkMux::Lsi32k),
+        });
+
+        let mut sources = [self.freq_monitor.sys, wdt, cpu_rtc]
+            .into_iter()
+            .chain(timer32_async)
+            .chain(timer16);
+        if sources.all(|source| self.is_enabled(source)) {
+            Ok(())
+        } else {
+            Err(Error::SourceDisabled)
+        }
+    }
+
+    /// Calculates the register values `init` writes for this configuration
+    fn register_values(&self) -> RegisterValues {
+        let sysclk = match self.freq_monitor.sys {
+            AhbClkMux::Osc32m => 0,
+            AhbClkMux::Hsi32m => 1,
+            AhbClkMux::Osc32k => 2,
+            AhbClkMux::Lsi32k => 3,
+        };
+        let force_mux = match self.freq_monitor.force_osc_sys {
+            ForceMux::Unfixed => 0,
+            ForceMux::Fixed => 1,
+        };
+        let cpu_rtc_clk_mux = match self.rtccpuclk {
+            CpuRtcClkMux::Osc32k => 0,
+            CpuRtcClkMux::Lsi32k => 1,
+        };
+        let wdt_clk_mux = match self.wdtclk {
+            WdtClkMux::Osc32m => 0,
+            WdtClkMux::Hsi32m => 1,
+            WdtClkMux::Osc32k => 2,
+            WdtClkMux::Lsi32k => 3,
+        };
+        let force_32k_clk = match self.freq_monitor.force32k_clk {
+            Force32kClk::Automatic => 0,
+            Force32kClk::Lsi32k => 1,
+            Force32kClk::Osc32k => 2,
+        };
+        let rtc_clk_mux = match self.rtcclk {
+            RtcClkMux::Automatic => 0,
+            RtcClkMux::Lsi32k => 1,
+            RtcClkMux::Osc32k => 2,
+        };
+
+        // Each timer owns a 3 bit slot: `Timer32_n` TIM1/TIM2 at bits 3n/3n+1,
+        // `Timer16_n` at bits 9+3n
+        let mut timer_cfg = 0;
+        for n in 0..3 {
+            timer_cfg |= (self.timer32_sync[n] as u32) << (3 * n);
+            timer_cfg |= (self.timer32_async[n] as u32) << (3 * n + 1);
+            timer_cfg |= (self.timer16[n] as u32) << (9 + 3 * n);
+        }
+
+        RegisterValues {
+            ahb_mux: force_mux << 2 | sysclk,
+            div_ahb: self.ahb_divider as u32 - 1,
+            div_apb_m: self.apb_m_divider as u32 - 1,
+            div_apb_p: self.apb_p_divider as u32 - 1,
+            cpu_rtc_clk_mux,
+            wdt_clk_mux,
+            timer_cfg,
+            clocks_sys: force_32k_clk << 10 | (self.hsi32m_calibration_value as u32) << 2,
+            clocks_bu: rtc_clk_mux << 10 | (self.lsi32k_calibration_value as u32) << 6,
+        }
+    }
+
+    pub fn init(config: Config) -> Result<Clocks, Error> {
+        let clocks = config.clocks()?;
+        let regs = config.register_values();
+
         let wu = unsafe { WakeUp::steal() };
         let pm = unsafe { Pm::steal() };
         wu.clocks_sys().modify(|_, w| w
@@ -83,42 +493,26 @@ impl Config {
             .osc32k_en().enable()
         );
 
-        wu.clocks_sys().modify(|_, w| unsafe { w.adj_hsi32m().bits(config.hsi32m_calibration_value) });
-        wu.clocks_bu().modify(|_, w| unsafe { w.adj_lsi32k().bits(config.lsi32k_calibration_value) });
-
-        wu.clocks_sys().modify(|_, w| match config.freq_monitor.force32k_clk {
-            Force32kClk::Automatic => w.force_32k_clk().automatic(),
-            Force32kClk::Lsi32k => w.force_32k_clk().lsi32k(),
-            Force32kClk::Osc32k => w.force_32k_clk().osc32k(),
-        });
-
-        pm.ahb_mux().modify(|_, w| match config.freq_monitor.force_osc_sys {
-            ForceMux::Unfixed => w.force_mux().unfixed(),
-            ForceMux::Fixed => w.force_mux().fixed(),
-        });
-
-        pm.ahb_mux().modify(|_, w| match config.freq_monitor.sys {
-            AhbClkMux::Osc32m => w.ahb_clk_mux().osc32m(),
-            AhbClkMux::Hsi32m => w.ahb_clk_mux().hsi32m(),
-            AhbClkMux::Osc32k => w.ahb_clk_mux().osc32k(),
-            AhbClkMux::Lsi32k => w.ahb_clk_mux().lsi32k(),
+        wu.clocks_sys().modify(|r, w| unsafe {
+            w.bits((r.bits() & !CLOCKS_SYS_CONFIG_MASK) | regs.clocks_sys)
         });
-
-        pm.div_ahb().modify(|_, w| unsafe { w.bits(config.ahb_div as u32) });
-        pm.div_apb_m().modify(|_, w| unsafe { w.bits(config.apb_m_div as u32) });
-        pm.div_apb_m().modify(|_, w| unsafe { w.bits(config.apb_m_div as u32) });
-
-        wu.clocks_bu().modify(|_, w| match config.rtcclk {
-            RtcClkMux::Automatic => w.rtc_clk_mux().automatic(),
-            RtcClkMux::Lsi32k => w.rtc_clk_mux().lsi32k(),
-            RtcClkMux::Osc32k => w.rtc_clk_mux().osc32k(),
+        wu.clocks_bu().modify(|r, w| unsafe {
+            w.bits((r.bits() & !CLOCKS_BU_CONFIG_MASK) | regs.clocks_bu)
         });
         wu.rtc_control().reset();
 
-        pm.cpu_rtc_clk_mux().modify(|_, w| match config.rtccpuclk{
-            CpuRtcClkMux::Osc32k => w.cpu_rtc_clk_mux().osc32k(),
-            CpuRtcClkMux::Lsi32k => w.cpu_rtc_clk_mux().osc32k(),
-        });
+        // The switch itself cannot be observed: `AHB_MUX` only reads back the written
+        // selection and `FREQ_STATUS` reports which oscillators run, not which one drives the
+        // core. The new source is therefore checked before it is selected.
+        wait_source_ready(&pm, config.freq_monitor.sys)?;
+        pm.ahb_mux().write(|w| unsafe { w.bits(regs.ahb_mux) });
+
+        pm.div_ahb().write(|w| unsafe { w.bits(regs.div_ahb) });
+        pm.div_apb_m().write(|w| unsafe { w.bits(regs.div_apb_m) });
+        pm.div_apb_p().write(|w| unsafe { w.bits(regs.div_apb_p) });
+        pm.cpu_rtc_clk_mux().write(|w| unsafe { w.bits(regs.cpu_rtc_clk_mux) });
+        pm.wdt_clk_mux().write(|w| unsafe { w.bits(regs.wdt_clk_mux) });
+        pm.timer_cfg().write(|w| unsafe { w.bits(regs.timer_cfg) });
 
         if !config.osc32m {
             wu.clocks_sys().modify(|_, w| w
@@ -143,5 +537,442 @@ impl Config {
                 .lsi32k_en().disable()
             );
         }
+
+        Ok(clocks)
+    }
+}
+
+/// Waits until the frequency monitor reports that `source` is running
+fn wait_source_ready(pm: &Pm, source: AhbClkMux) -> Result<(), Error> {
+    for _ in 0..CLOCKSWITCH_TIMEOUT_VALUE {
+        if source_running(pm, source) {
+            return Ok(());
+        }
+    }
+
+    Err(match source {
+        AhbClkMux::Osc32m => Error::Osc32mNotReady,
+        AhbClkMux::Hsi32m => Error::Hsi32mNotReady,
+        AhbClkMux::Osc32k => Error::Osc32kNotReady,
+        AhbClkMux::Lsi32k => Error::Lsi32kNotReady,
+    })
+}
+
+/// Returns `true` if the frequency monitor detects the frequency of `source`
+fn source_running(pm: &Pm, source: AhbClkMux) -> bool {
+    let status = pm.freq_status().read();
+    match source {
+        AhbClkMux::Osc32m => status.mask_osc32m().bit_is_set(),
+        AhbClkMux::Hsi32m => status.mask_hsi32m().bit_is_set(),
+        AhbClkMux::Osc32k => status.mask_osc32k().bit_is_set(),
+        AhbClkMux::Lsi32k => status.mask_lsi32k().bit_is_set(),
+    }
+}
+
+/// Bus clock gating of a peripheral through the PM `CLK_*_SET` / `CLK_*_CLEAR` registers
+///
+/// The PM block has no peripheral reset registers, so gating the clock is the only control.
+pub trait Enable {
+    /// Enables the bus clock of the peripheral
+    fn enable(pm: &mik32v2_pac::pm::RegisterBlock);
+
+    /// Disables the bus clock of the peripheral
+    fn disable(pm: &mik32v2_pac::pm::RegisterBlock);
+
+    /// Returns `true` if the bus clock of the peripheral is enabled
+    fn is_enabled(pm: &mik32v2_pac::pm::RegisterBlock) -> bool;
+}
+
+macro_rules! bus_enable {
+    ($(
+        $PER:ident: ($set:ident, $clear:ident, $field:ident),
+    )+) => {
+        $(
+            impl Enable for $PER {
+                #[inline(always)]
+                fn enable(pm: &mik32v2_pac::pm::RegisterBlock) {
+                    // NOTE(unsafe) writing zero to the other bits of a set register has no effect
+                    unsafe { pm.$set().write_with_zero(|w| w.$field().set_bit()); }
+                }
+
+                #[inline(always)]
+                fn disable(pm: &mik32v2_pac::pm::RegisterBlock) {
+                    // NOTE(unsafe) writing zero to the other bits of a clear register has no effect
+                    unsafe { pm.$clear().write_with_zero(|w| w.$field().set_bit()); }
+                }
+
+                #[inline(always)]
+                fn is_enabled(pm: &mik32v2_pac::pm::RegisterBlock) -> bool {
+                    pm.$set().read().$field().bit_is_set()
+                }
+            }
+        )+
+    }
+}
+
+bus_enable! {
+    EepromRegs: (clk_ahb_set, clk_ahb_clear, eeprom),
+    SpifiConfig: (clk_ahb_set, clk_ahb_clear, spifi),
+    Dma: (clk_ahb_set, clk_ahb_clear, dma),
+    Crypto: (clk_ahb_set, clk_ahb_clear, crypto),
+    Crc: (clk_ahb_set, clk_ahb_clear, crc32),
+
+    // PM, PAD_CONFIG and WU are clocked out of reset and used by the HAL itself, they are
+    // never gated. EPIC is gated out of reset, every driver unmasking a line enables it.
+    Epic: (clk_apb_m_set, clk_apb_m_clear, epic),
+    Timer32_0: (clk_apb_m_set, clk_apb_m_clear, timer32_0),
+    WdtBus: (clk_apb_m_set, clk_apb_m_clear, wdt_bus),
+    Otp: (clk_apb_m_set, clk_apb_m_clear, otp),
+    PvdVcc: (clk_apb_m_set, clk_apb_m_clear, pvd),
+    Rtc: (clk_apb_m_set, clk_apb_m_clear, rtc),
+
+    Wdt: (clk_apb_p_set, clk_apb_p_clear, wdt),
+    Usart0: (clk_apb_p_set, clk_apb_p_clear, uart_0),
+    Usart1: (clk_apb_p_set, clk_apb_p_clear, uart_1),
+    Timer16_0: (clk_apb_p_set, clk_apb_p_clear, timer16_0),
+    Timer16_1: (clk_apb_p_set, clk_apb_p_clear, timer16_1),
+    Timer16_2: (clk_apb_p_set, clk_apb_p_clear, timer16_2),
+    Timer32_1: (clk_apb_p_set, clk_apb_p_clear, timer32_1),
+    Timer32_2: (clk_apb_p_set, clk_apb_p_clear, timer32_2),
+    Spi0: (clk_apb_p_set, clk_apb_p_clear, spi_0),
+    Spi1: (clk_apb_p_set, clk_apb_p_clear, spi_1),
+    I2c0: (clk_apb_p_set, clk_apb_p_clear, i2c_0),
+    I2c1: (clk_apb_p_set, clk_apb_p_clear, i2c_1),
+    Gpio16_0: (clk_apb_p_set, clk_apb_p_clear, gpio_0),
+    Gpio16_1: (clk_apb_p_set, clk_apb_p_clear, gpio_1),
+    Gpio8_2: (clk_apb_p_set, clk_apb_p_clear, gpio_2),
+    GpioIrq: (clk_apb_p_set, clk_apb_p_clear, gpio_irq),
+    // The analog blocks share one gate, disabling any of them disables all of them
+    AnalogRegs: (clk_apb_p_set, clk_apb_p_clear, analog_regs),
+    Adc: (clk_apb_p_set, clk_apb_p_clear, analog_regs),
+    Dac0: (clk_apb_p_set, clk_apb_p_clear, analog_regs),
+    Dac1: (clk_apb_p_set, clk_apb_p_clear, analog_regs),
+}
+
+/// Clock gate shared by all analog blocks
+///
+/// The ADC, both DACs, the temperature sensor, the AVCC voltage monitor and the reference
+/// voltage configuration have no gates of their own. Disabling this gate stops all of them.
+pub struct AnalogRegs {
+    _private: (),
+}
+
+/// Frequency monitor failure callback
+static FREQ_MONITOR_CALLBACK: Mutex<Cell<Option<fn(AhbClkMux)>>> = Mutex::new(Cell::new(None));
+
+const FREQ_MONITOR_SOURCES: [AhbClkMux; 4] = [
+    AhbClkMux::Osc32m,
+    AhbClkMux::Hsi32m,
+    AhbClkMux::Osc32k,
+    AhbClkMux::Lsi32k,
+];
+
+/// Runtime access to the clock frequency monitor
+///
+/// The monitor watches every oscillator and, unless the system clock source is fixed with
+/// [`ForceMux::Fixed`], switches the system clock away from a failed source on its own.
+pub struct FrequencyMonitor {
+    _private: (),
+}
+
+impl FrequencyMonitor {
+    pub fn new(_clocks: &Clocks) -> Self {
+        Self { _private: () }
+    }
+
+    /// Enables the monitor interrupt for the loss of `source`
+    pub fn listen(&mut self, source: AhbClkMux) {
+        // NOTE(unsafe) the frequency monitor registers are only owned by this driver
+        let pm = unsafe { &(*Pm::ptr()) };
+        let epic = unsafe { &(*Epic::ptr()) };
+        Epic::enable(pm);
+
+        pm.freq_mask().modify(|_, w| match source {
+            AhbClkMux::Osc32m => w.mask_osc32m().enable(),
+            AhbClkMux::Hsi32m => w.mask_hsi32m().enable(),
+            AhbClkMux::Osc32k => w.mask_osc32k().enable(),
+            AhbClkMux::Lsi32k => w.mask_lsi32k().enable(),
+        });
+        epic.mask_level_set().write(|w| w.frequency_monitor().set_bit());
+    }
+
+    /// Disables the monitor interrupt for the loss of `source`
+    pub fn unlisten(&mut self, source: AhbClkMux) {
+        // NOTE(unsafe) the frequency monitor registers are only owned by this driver
+        let pm = unsafe { &(*Pm::ptr()) };
+        let epic = unsafe { &(*Epic::ptr()) };
+
+        pm.freq_mask().modify(|_, w| match source {
+            AhbClkMux::Osc32m => w.mask_osc32m().disable(),
+            AhbClkMux::Hsi32m => w.mask_hsi32m().disable(),
+            AhbClkMux::Osc32k => w.mask_osc32k().disable(),
+            AhbClkMux::Lsi32k => w.mask_lsi32k().disable(),
+        });
+        if pm.freq_mask().read().bits() == 0 {
+            epic.mask_level_clear().write(|w| w.frequency_monitor().set_bit());
+        }
+    }
+
+    /// Registers a function called from [`FrequencyMonitor::on_interrupt`] for every failed source
+    pub fn set_callback(&mut self, callback: fn(AhbClkMux)) {
+        critical_section::with(|cs| FREQ_MONITOR_CALLBACK.borrow(cs).set(Some(callback)));
+    }
+
+    /// Returns `true` if the monitor detects the frequency of `source`
+    pub fn is_running(&self, source: AhbClkMux) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        let status = unsafe { (*Pm::ptr()).freq_status().read() };
+        match source {
+            AhbClkMux::Osc32m => status.mask_osc32m().bit_is_set(),
+            AhbClkMux::Hsi32m => status.mask_hsi32m().bit_is_set(),
+            AhbClkMux::Osc32k => status.mask_osc32k().bit_is_set(),
+            AhbClkMux::Lsi32k => status.mask_lsi32k().bit_is_set(),
+        }
+    }
+
+    /// Returns the first listened source whose frequency has been lost
+    pub fn failed(&self) -> Option<AhbClkMux> {
+        // NOTE(unsafe) atomic read with no side effects
+        let mask = unsafe { (*Pm::ptr()).freq_mask().read() };
+        FREQ_MONITOR_SOURCES.into_iter().find(|&source| {
+            let listened = match source {
+                AhbClkMux::Osc32m => mask.mask_osc32m().is_enable(),
+                AhbClkMux::Hsi32m => mask.mask_hsi32m().is_enable(),
+                AhbClkMux::Osc32k => mask.mask_osc32k().is_enable(),
+                AhbClkMux::Lsi32k => mask.mask_lsi32k().is_enable(),
+            };
+            listened && !self.is_running(source)
+        })
+    }
+
+    /// Returns the source the system clock currently runs from
+    ///
+    /// `AHB_MUX` only reads back the configured selection, the automatic switch is not
+    /// visible in any register. The active source is therefore derived from `FREQ_STATUS`:
+    /// while the configured source runs, or the selection is fixed with [`ForceMux::Fixed`],
+    /// it is the configured source. Otherwise it is the first running source in the
+    /// monitor's fallback order `OSC32M`, `HSI32M`, `OSC32K`, `LSI32K`.
+    pub fn active_source(&self) -> AhbClkMux {
+        // NOTE(unsafe) atomic read with no side effects
+        let ahb_mux = unsafe { (*Pm::ptr()).ahb_mux().read() };
+        let configured = ahb_mux.ahb_clk_mux().variant();
+        if ahb_mux.force_mux().is_fixed() || self.is_running(configured) {
+            return configured;
+        }
+        FREQ_MONITOR_SOURCES
+            .into_iter()
+            .find(|&source| self.is_running(source))
+            .unwrap_or(configured)
     }
-}
\ No newline at end of file
+
+    /// Handles the frequency monitor interrupt
+    ///
+    /// Must be called from the trap handler when the EPIC reports the frequency monitor line.
+    /// Every failed source is reported to the registered callback and its interrupt is
+    /// masked, since the line stays active for as long as the oscillator is missing.
+    pub fn on_interrupt() {
+        // NOTE(unsafe) only called from the interrupt handler
+        let mut monitor = Self { _private: () };
+        let epic = unsafe { &(*Epic::ptr()) };
+        let callback = critical_section::with(|cs| FREQ_MONITOR_CALLBACK.borrow(cs).get());
+
+        while let Some(source) = monitor.failed() {
+            monitor.unlisten(source);
+            if let Some(callback) = callback {
+                callback(source);
+            }
+        }
+
+        epic.clear().write(|w| w.frequency_monitor().set_bit());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_runs_everything_at_32mhz() {
+        let clocks = Config::default().clocks().unwrap();
+        assert_eq!(clocks.sysclk(), Hertz(32_000_000));
+        assert_eq!(clocks.ahb(), Hertz(32_000_000));
+        assert_eq!(clocks.apb_m(), Hertz(32_000_000));
+        assert_eq!(clocks.apb_p(), Hertz(32_000_000));
+    }
+
+    #[test]
+    fn bus_dividers_scale_the_bus_clocks() {
+        let clocks = Config::default()
+            .ahb_div(2)
+            .apb_m_div(4)
+            .apb_p_div(8)
+            .clocks()
+            .unwrap();
+        assert_eq!(clocks.sysclk(), Hertz(32_000_000));
+        assert_eq!(clocks.ahb(), Hertz(16_000_000));
+        assert_eq!(clocks.apb_m(), Hertz(4_000_000));
+        assert_eq!(clocks.apb_p(), Hertz(2_000_000));
+    }
+
+    #[test]
+    fn apb_p_divider_is_independent_of_apb_m() {
+        let clocks = Config::default().apb_m_div(2).clocks().unwrap();
+        assert_eq!(clocks.apb_m(), Hertz(16_000_000));
+        assert_eq!(clocks.apb_p(), Hertz(32_000_000));
+    }
+
+    #[test]
+    fn zero_divider_is_rejected() {
+        assert_eq!(Config::default().ahb_div(0).clocks().err(), Some(Error::InvalidDivider));
+        assert_eq!(Config::default().apb_m_div(0).clocks().err(), Some(Error::InvalidDivider));
+        assert_eq!(Config::default().apb_p_div(0).clocks().err(), Some(Error::InvalidDivider));
+    }
+
+    #[test]
+    fn divider_range_ends_at_max_bus_div() {
+        let clocks = Config::default().ahb_div(MAX_BUS_DIV).clocks().unwrap();
+        assert_eq!(clocks.ahb(), Hertz(32_000_000 / MAX_BUS_DIV as u32));
+
+        let config = Config::default().apb_p_div(MAX_BUS_DIV + 1);
+        assert_eq!(config.clocks().err(), Some(Error::InvalidDivider));
+    }
+
+    #[test]
+    fn lsi32k_calibration_range_is_checked() {
+        assert!(Config::default().lsi32k_calibration(MAX_LSI32K_CALIBRATION).clocks().is_ok());
+
+        let config = Config::default().lsi32k_calibration(MAX_LSI32K_CALIBRATION + 1);
+        assert_eq!(config.clocks().err(), Some(Error::InvalidCalibration));
+    }
+
+    #[test]
+    fn disabled_sysclk_source_is_rejected() {
+        let config = Config {
+            osc32m: false,
+            ..Config::default()
+        }
+        .wdt_clock(WdtClkMux::Hsi32m);
+        assert_eq!(config.clocks().err(), Some(Error::SourceDisabled));
+
+        let clocks = config.sysclk_source(AhbClkMux::Hsi32m).clocks().unwrap();
+        assert_eq!(clocks.sysclk(), HSI32M_FREQ);
+    }
+
+    #[test]
+    fn disabled_wdt_source_is_rejected() {
+        let config = Config {
+            lsi32k: false,
+            ..Config::default()
+        };
+        assert!(config.clocks().is_ok());
+
+        let config = config.wdt_clock(WdtClkMux::Lsi32k);
+        assert_eq!(config.clocks().err(), Some(Error::SourceDisabled));
+    }
+
+    #[test]
+    fn disabled_timer_source_is_rejected() {
+        let mut config = Config {
+            hsi32m: false,
+            osc32k: false,
+            ..Config::default()
+        }
+        .cpu_rtc_clock(CpuRtcClkMux::Lsi32k);
+        config.timer32_async = [Timer32AsyncClk::Lsi32k; 3];
+        assert!(config.clocks().is_ok());
+
+        config = config.timer16_clock(Timer16Id::Timer16_1, Timer16Clk::Hsi32m);
+        assert_eq!(config.clocks().err(), Some(Error::SourceDisabled));
+
+        config = config
+            .timer16_clock(Timer16Id::Timer16_1, Timer16Clk::Lsi32k)
+            .timer32_clocks(Timer32Id::Timer32_2, Timer32SyncClk::SysClk, Timer32AsyncClk::Osc32k);
+        assert_eq!(config.clocks().err(), Some(Error::SourceDisabled));
+    }
+
+    #[test]
+    fn timer_clocks_follow_their_source() {
+        let clocks = Config::default()
+            .ahb_div(4)
+            .timer32_clocks(Timer32Id::Timer32_1, Timer32SyncClk::Hclk, Timer32AsyncClk::Lsi32k)
+            .timer16_clock(Timer16Id::Timer16_2, Timer16Clk::Osc32k)
+            .clocks()
+            .unwrap();
+        assert_eq!(clocks.timer32_sync(Timer32Id::Timer32_0), Hertz(32_000_000));
+        assert_eq!(clocks.timer32_sync(Timer32Id::Timer32_1), Hertz(8_000_000));
+        assert_eq!(clocks.timer32_async(Timer32Id::Timer32_1), LSI32K_FREQ);
+        assert_eq!(clocks.timer16(Timer16Id::Timer16_0), Hertz(32_000_000));
+        assert_eq!(clocks.timer16(Timer16Id::Timer16_2), OSC32K_FREQ);
+    }
+
+    #[test]
+    fn default_registers() {
+        let regs = Config::default().register_values();
+        assert_eq!(
+            regs,
+            RegisterValues {
+                ahb_mux: 0,
+                div_ahb: 0,
+                div_apb_m: 0,
+                div_apb_p: 0,
+                cpu_rtc_clk_mux: 0,
+                wdt_clk_mux: 0,
+                timer_cfg: 0,
+                clocks_sys: 128 << 2,
+                clocks_bu: 8 << 6,
+            }
+        );
+    }
+
+    #[test]
+    fn each_divider_is_written_to_its_own_register() {
+        let regs = Config::default()
+            .ahb_div(2)
+            .apb_m_div(4)
+            .apb_p_div(MAX_BUS_DIV)
+            .register_values();
+        assert_eq!(regs.div_ahb, 1);
+        assert_eq!(regs.div_apb_m, 3);
+        assert_eq!(regs.div_apb_p, 255);
+    }
+
+    #[test]
+    fn clock_muxes_are_written_with_the_selected_source() {
+        let mut config = Config::default()
+            .sysclk_source(AhbClkMux::Lsi32k)
+            .cpu_rtc_clock(CpuRtcClkMux::Lsi32k)
+            .wdt_clock(WdtClkMux::Osc32k)
+            .rtc_clock(RtcClkMux::Osc32k);
+        config.freq_monitor.force_osc_sys = ForceMux::Fixed;
+        config.freq_monitor.force32k_clk = Force32kClk::Lsi32k;
+
+        let regs = config.register_values();
+        assert_eq!(regs.ahb_mux, 0b111);
+        assert_eq!(regs.cpu_rtc_clk_mux, 1);
+        assert_eq!(regs.wdt_clk_mux, 2);
+        assert_eq!(regs.clocks_sys & !(0xFF << 2), 1 << 10);
+        assert_eq!(regs.clocks_bu & !(0xF << 6), 2 << 10);
+    }
+
+    #[test]
+    fn calibration_values_stay_inside_their_fields() {
+        let regs = Config::default()
+            .hsi32m_calibration(0xFF)
+            .lsi32k_calibration(MAX_LSI32K_CALIBRATION)
+            .register_values();
+        assert_eq!(regs.clocks_sys, 0xFF << 2);
+        assert_eq!(regs.clocks_bu, 0xF << 6);
+        assert_eq!(regs.clocks_sys & !CLOCKS_SYS_CONFIG_MASK, 0);
+        assert_eq!(regs.clocks_bu & !CLOCKS_BU_CONFIG_MASK, 0);
+    }
+
+    #[test]
+    fn timer_sources_are_written_to_their_slots() {
+        let regs = Config::default()
+            .timer32_clocks(Timer32Id::Timer32_0, Timer32SyncClk::Hclk, Timer32AsyncClk::Osc32k)
+            .timer32_clocks(Timer32Id::Timer32_2, Timer32SyncClk::SysClk, Timer32AsyncClk::Lsi32k)
+            .timer16_clock(Timer16Id::Timer16_1, Timer16Clk::Lsi32k)
+            .register_values();
+        assert_eq!(regs.timer_cfg, 1 | 1 << 7 | 5 << 12);
+    }
+}
@@ -0,0 +1,301 @@
+//! Trimming of the internal RC oscillators
+//!
+//! The RC oscillator under test and the reference crystal clock two 16 bit timers through the
+//! `TIMER_CFG` multiplexers. The slower clock opens a measurement window on `Timer16_0` while
+//! `Timer16_1` counts the faster one, and the trim value is binary-searched until the count
+//! matches the one expected from the nominal frequencies.
+//!
+//! A timer counting a 32 MHz clock is prescaled by 128, otherwise its counter could change
+//! between every two bus reads and never return a stable value.
+
+use mik32v2_pac::{Pm, Timer16_0, Timer16_1, WakeUp};
+
+use super::{
+    Enable, Error, HSI32M_FREQ, LSI32K_FREQ, MAX_LSI32K_CALIBRATION, OSC32K_FREQ, OSC32M_FREQ,
+};
+use crate::time::Hertz;
+
+/// `CFGR.PRESC` value dividing the timer clock by 128
+const PRESC_128: u32 = 0b111;
+/// Position of `CFGR.PRESC`
+const PRESC_SHIFT: u32 = 9;
+/// Number of attempts to get two equal consecutive counter reads
+const READ_CNT_RETRIES: u32 = 16;
+/// Number of window counter reads without a change after which the window clock is
+/// considered stopped. A 32 kHz tick lasts 1 000 core cycles at 32 MHz.
+const WINDOW_TICK_READS: u32 = 10_000;
+
+/// Reference clock for the RC oscillator calibration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationReference {
+    /// External 32 kHz crystal
+    Osc32k,
+    /// External 32 MHz crystal
+    Osc32m,
+}
+
+/// Clock sources available to the `Timer16_x` multiplexers
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Osc32m,
+    Hsi32m,
+    Osc32k,
+    Lsi32k,
+}
+
+impl Source {
+    fn freq(self) -> Hertz {
+        match self {
+            Source::Osc32m => OSC32M_FREQ,
+            Source::Hsi32m => HSI32M_FREQ,
+            Source::Osc32k => OSC32K_FREQ,
+            Source::Lsi32k => LSI32K_FREQ,
+        }
+    }
+
+    /// `CFGR` value of a timer counting this source
+    fn cfgr(self) -> u32 {
+        match self {
+            Source::Osc32m | Source::Hsi32m => PRESC_128 << PRESC_SHIFT,
+            Source::Osc32k | Source::Lsi32k => 0,
+        }
+    }
+
+    /// Frequency seen by a timer counting this source, after its prescaler
+    fn counted_freq(self) -> Hertz {
+        match self {
+            Source::Osc32m | Source::Hsi32m => self.freq() / 128u32,
+            Source::Osc32k | Source::Lsi32k => self.freq(),
+        }
+    }
+
+    fn not_ready(self) -> Error {
+        match self {
+            Source::Osc32m => Error::Osc32mNotReady,
+            Source::Hsi32m => Error::Hsi32mNotReady,
+            Source::Osc32k => Error::Osc32kNotReady,
+            Source::Lsi32k => Error::Lsi32kNotReady,
+        }
+    }
+
+    fn is_running(self, pm: &mik32v2_pac::pm::RegisterBlock) -> bool {
+        let status = pm.freq_status().read();
+        match self {
+            Source::Osc32m => status.mask_osc32m().bit_is_set(),
+            Source::Hsi32m => status.mask_hsi32m().bit_is_set(),
+            Source::Osc32k => status.mask_osc32k().bit_is_set(),
+            Source::Lsi32k => status.mask_lsi32k().bit_is_set(),
+        }
+    }
+}
+
+/// Finds the `ADJ_HSI32M` value that brings HSI32M closest to 32 MHz
+///
+/// `Timer16_0` and `Timer16_1` are borrowed for the measurement and stopped afterwards.
+/// The best value is left applied when this returns, the previous one is restored on error.
+pub fn calibrate_hsi32m(
+    reference: CalibrationReference,
+    _tim0: &mut Timer16_0,
+    _tim1: &mut Timer16_1,
+) -> Result<u8, Error> {
+    let (window, counted, ticks) = match reference {
+        CalibrationReference::Osc32k => (Source::Osc32k, Source::Hsi32m, 1_024),
+        CalibrationReference::Osc32m => (Source::Osc32m, Source::Hsi32m, 8_000),
+    };
+
+    // NOTE(unsafe) the calibration owns the trimming field while it runs
+    let wu = unsafe { &(*WakeUp::ptr()) };
+    let initial = wu.clocks_sys().read().adj_hsi32m().bits();
+    calibrate(Source::Hsi32m, window, counted, ticks, u8::MAX, initial, |value| {
+        wu.clocks_sys().modify(|_, w| unsafe { w.adj_hsi32m().bits(value) });
+    })
+}
+
+/// Finds the `ADJ_LSI32K` value that brings LSI32K closest to 32 kHz
+///
+/// `Timer16_0` and `Timer16_1` are borrowed for the measurement and stopped afterwards.
+/// The best value is left applied when this returns, the previous one is restored on error.
+pub fn calibrate_lsi32k(
+    reference: CalibrationReference,
+    _tim0: &mut Timer16_0,
+    _tim1: &mut Timer16_1,
+) -> Result<u8, Error> {
+    let (window, counted, ticks) = match reference {
+        CalibrationReference::Osc32k => (Source::Lsi32k, Source::Osc32k, 2_000),
+        CalibrationReference::Osc32m => (Source::Lsi32k, Source::Osc32m, 256),
+    };
+
+    // NOTE(unsafe) the calibration owns the trimming field while it runs
+    let wu = unsafe { &(*WakeUp::ptr()) };
+    let initial = wu.clocks_bu().read().adj_lsi32k().bits();
+    calibrate(Source::Lsi32k, window, counted, ticks, MAX_LSI32K_CALIBRATION, initial, |value| {
+        wu.clocks_bu().modify(|_, w| unsafe { w.adj_lsi32k().bits(value) });
+    })
+}
+
+fn calibrate(
+    trimmed: Source,
+    window: Source,
+    counted: Source,
+    ticks: u16,
+    max: u8,
+    initial: u8,
+    apply: impl Fn(u8),
+) -> Result<u8, Error> {
+    // NOTE(unsafe) the timers are borrowed by the caller for the whole calibration
+    let pm = unsafe { &(*Pm::ptr()) };
+    let tim0 = unsafe { &(*Timer16_0::ptr()) };
+    let tim1 = unsafe { &(*(Timer16_1::ptr() as *const mik32v2_pac::timer16_0::RegisterBlock)) };
+
+    let reference = if window == trimmed { counted } else { window };
+    for source in [reference, trimmed] {
+        if !source.is_running(pm) {
+            return Err(source.not_ready());
+        }
+    }
+
+    let tim0_enabled = Timer16_0::is_enabled(pm);
+    let tim1_enabled = Timer16_1::is_enabled(pm);
+    let timer_cfg = pm.timer_cfg().read().bits();
+    Timer16_0::enable(pm);
+    Timer16_1::enable(pm);
+
+    pm.timer_cfg().modify(|_, w| match window {
+        Source::Osc32m => w.mux_tim16_0().osc32m(),
+        Source::Hsi32m => w.mux_tim16_0().hsi32m(),
+        Source::Osc32k => w.mux_tim16_0().osc32k(),
+        Source::Lsi32k => w.mux_tim16_0().lsi32k(),
+    });
+    pm.timer_cfg().modify(|_, w| match counted {
+        Source::Osc32m => w.mux_tim16_1().osc32m(),
+        Source::Hsi32m => w.mux_tim16_1().hsi32m(),
+        Source::Osc32k => w.mux_tim16_1().osc32k(),
+        Source::Lsi32k => w.mux_tim16_1().lsi32k(),
+    });
+
+    for (tim, source) in [(tim0, window), (tim1, counted)] {
+        tim.cr().reset();
+        // NOTE(unsafe) only the prescaler is set, the configuration is written while disabled
+        tim.cfgr().write(|w| unsafe { w.bits(source.cfgr()) });
+        tim.cr().write(|w| w.enable().set_bit());
+        tim.arr().write(|w| unsafe { w.arr().bits(u16::MAX) });
+        tim.cr().modify(|_, w| w.cntstrt().set_bit());
+    }
+
+    let result = search(trimmed, window, counted, ticks, max, |value| {
+        apply(value);
+        measure_window(tim0, tim1, window, ticks)
+    });
+    match result {
+        Ok(best) => apply(best),
+        Err(_) => apply(initial),
+    }
+
+    for tim in [tim0, tim1] {
+        tim.cr().reset();
+        tim.cfgr().reset();
+    }
+    pm.timer_cfg().write(|w| unsafe { w.bits(timer_cfg) });
+    if !tim0_enabled {
+        Timer16_0::disable(pm);
+    }
+    if !tim1_enabled {
+        Timer16_1::disable(pm);
+    }
+
+    result
+}
+
+/// Binary-searches the trimming value, `measure` applies a value and returns the window count
+fn search(
+    trimmed: Source,
+    window: Source,
+    counted: Source,
+    ticks: u16,
+    max: u8,
+    mut measure: impl FnMut(u8) -> Result<u32, Error>,
+) -> Result<u8, Error> {
+    let expected =
+        (ticks as u64 * counted.counted_freq().0 as u64 / window.counted_freq().0 as u64) as u32;
+
+    // The direction in which the trimming value moves the frequency is found first,
+    // the window count falls as the trimmed oscillator speeds up when it opens the window
+    let rising = (measure(max)? > measure(0)?) ^ matches!(trimmed, Source::Lsi32k);
+
+    let (mut low, mut high) = (0u8, max);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let count = measure(mid)?;
+        let too_slow = if matches!(trimmed, Source::Lsi32k) {
+            count > expected
+        } else {
+            count < expected
+        };
+        if too_slow == rising {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    // The search stops at the first value past the target, its neighbour may be closer
+    if low > 0 && measure(low - 1)?.abs_diff(expected) < measure(low)?.abs_diff(expected) {
+        Ok(low - 1)
+    } else {
+        Ok(low)
+    }
+}
+
+/// Counts `Timer16_1` ticks during `ticks` periods of the `Timer16_0` clock
+///
+/// Fails with the `NotReady` error of `window` if its clock stops.
+fn measure_window(
+    tim0: &mik32v2_pac::timer16_0::RegisterBlock,
+    tim1: &mik32v2_pac::timer16_0::RegisterBlock,
+    window: Source,
+    ticks: u16,
+) -> Result<u32, Error> {
+    // Align the window to a `Timer16_0` edge
+    let start = read_cnt(tim0)?;
+    let window_start = next_tick(tim0, start, window)?;
+    let count_start = read_cnt(tim1)?;
+
+    let mut now = window_start;
+    while now.wrapping_sub(window_start) < ticks {
+        now = next_tick(tim0, now, window)?;
+    }
+    let count_end = read_cnt(tim1)?;
+
+    Ok(count_end.wrapping_sub(count_start) as u32)
+}
+
+/// Waits until the counter of `tim`, clocked by `source`, moves away from `current`
+fn next_tick(
+    tim: &mik32v2_pac::timer16_0::RegisterBlock,
+    current: u16,
+    source: Source,
+) -> Result<u16, Error> {
+    for _ in 0..WINDOW_TICK_READS {
+        let cnt = read_cnt(tim)?;
+        if cnt != current {
+            return Ok(cnt);
+        }
+    }
+
+    Err(source.not_ready())
+}
+
+/// Reads the counter of a timer running from an asynchronous clock
+///
+/// The value is only valid once two consecutive reads agree. The counted clocks are slow
+/// enough for this to happen within a few attempts, `UnstableCounter` is returned otherwise.
+fn read_cnt(tim: &mik32v2_pac::timer16_0::RegisterBlock) -> Result<u16, Error> {
+    for _ in 0..READ_CNT_RETRIES {
+        let first = tim.cnt().read().cnt().bits();
+        if tim.cnt().read().cnt().bits() == first {
+            return Ok(first);
+        }
+    }
+
+    Err(Error::UnstableCounter)
+}
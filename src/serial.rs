@@ -1,16 +1,78 @@
-use core::{error::Error, fmt, marker::PhantomData, ops::Deref};
-use mik32v2_pac::{crypto::config, usart_0::flags, Pm, Usart0, Usart1};
+//! Serial communication using USART
+//!
+//! # IrDA
+//!
+//! The MIK32 USART has no IrDA SIR encoder: `CONTROL3` has no IrDA enable or low-power
+//! bit and there is no prescaler register for the pulse width. An IR transceiver has to
+//! be driven through an external SIR encoder/decoder, with `invert_tx` and `invert_rx`
+//! in [`Config`] matching the polarity of the encoder.
+//!
+//! # Synchronous mode
+//!
+//! Synchronous (clocked) mode is not supported. `CONTROL2` has the `CLKEN`, `CPOL`, `CPHA`
+//! and `LBCL` bits of the clock output, but the pad function tables only bring out RXD,
+//! TXD, nCTS and nRTS of each USART: CK is not routed to any pad, so the clock never
+//! reaches a device. Use `SPI_0` or `SPI_1` for a clocked bus.
+//!
+//! # Smartcard
+//!
+//! There is no ISO 7816 mode either. The USART cannot send a NACK on a parity error,
+//! repeat a rejected character or insert guard time, and it has no clock output to drive
+//! the card. The character frame of T=0 can be approximated with [`HalfDuplex`]
+//! configured for 8 data bits, even parity and 2 stop bits, with the card clock coming
+//! from a timer output, but error signalling has to be handled by the protocol layer.
+
+use core::{fmt, marker::PhantomData, ops::Deref};
+use mik32v2_pac::{usart_0::flags, Epic, Pm, Usart0, Usart1};
 use embedded_hal_nb::serial::{ErrorKind, ErrorType, Read, Write};
 use nb::block;
 use rcc::Config as RccConfig;
 use riscv::register::mcounteren::write;
 use core::ptr;
 
-use crate::{gpio::{self, Func2Mode}, rcc};
+use crate::{gpio::{self, Alternate}, rcc::{self, Enable}};
+use crate::time::{Bps, Hertz};
+
+pub mod asynch;
+pub mod buffered;
+mod config;
+mod dma;
+mod half_duplex;
+pub mod lin;
+mod rs485;
+pub use asynch::{AsyncSerial, AsyncState};
+pub use buffered::{BufferedSerial, BufferedState};
+pub use config::{Config, FlowControl, Parity, StopBits, WordLength};
+pub use dma::{Overrun, RxCircular, TxTransfer};
+pub use half_duplex::HalfDuplex;
+pub use lin::{LinMaster, LinSlave};
+pub use rs485::{Rs485, Rs485Config};
+
+/// Maximum deviation of the achieved baud rate from the requested one, in per mille
+const BAUDRATE_TOLERANCE_PERMILLE: u32 = 25;
+
+/// Serial error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The requested baud rate cannot be reached from the APB_P clock
+    /// within the tolerance
+    BaudrateUnreachable,
+    /// 9 data bits cannot be combined with a parity bit
+    WordLengthUnsupported,
+    /// The configured flow control needs an RTS or CTS pin that was not provided
+    FlowControlPinMissing,
+}
 
-pub trait Pins<U> {}
+pub trait Pins<U> {
+    /// The pins include RTS
+    const RTS: bool = false;
+    /// The pins include CTS
+    const CTS: bool = false;
+}
 pub trait PinTx<U> {}
 pub trait PinRx<U> {}
+pub trait PinRts<U> {}
+pub trait PinCts<U> {}
 
 impl<U, TX, RX> Pins<U> for (TX, RX)
 where
@@ -19,13 +81,56 @@ where
 {
 }
 
+impl<U, TX, RX, RTS, CTS> Pins<U> for (TX, RX, RTS, CTS)
+where
+    TX: PinTx<U>,
+    RX: PinRx<U>,
+    RTS: PinRts<U>,
+    CTS: PinCts<U>,
+{
+    const RTS: bool = true;
+    const CTS: bool = true;
+}
+
 
-impl PinTx<Usart0> for gpio::P16_0_6<Func2Mode> {}
-impl PinRx<Usart0> for gpio::P16_0_5<Func2Mode> {}
+impl PinTx<Usart0> for gpio::P16_0_6<Alternate<2>> {}
+impl PinRx<Usart0> for gpio::P16_0_5<Alternate<2>> {}
+impl PinCts<Usart0> for gpio::P16_0_7<Alternate<2>> {}
+impl PinRts<Usart0> for gpio::P16_0_8<Alternate<2>> {}
 
-impl PinTx<Usart1> for gpio::P16_1_9<Func2Mode> {}
-impl PinRx<Usart1> for gpio::P16_1_8<Func2Mode> {}
+impl PinTx<Usart1> for gpio::P16_1_9<Alternate<2>> {}
+impl PinRx<Usart1> for gpio::P16_1_8<Alternate<2>> {}
+impl PinCts<Usart1> for gpio::P16_1_10<Alternate<2>> {}
+impl PinRts<Usart1> for gpio::P16_1_11<Alternate<2>> {}
 
+// A generic `(TX, RX, RTS)` impl would overlap with `(TX, RX, CTS)`, so the 3 pin sets
+// are implemented for the concrete RTS and CTS pins
+macro_rules! flow_control_pins {
+    ($($USART:ident: (rts: $RTS:ty, cts: $CTS:ty),)+) => {
+        $(
+            impl<TX, RX> Pins<$USART> for (TX, RX, $RTS)
+            where
+                TX: PinTx<$USART>,
+                RX: PinRx<$USART>,
+            {
+                const RTS: bool = true;
+            }
+
+            impl<TX, RX> Pins<$USART> for (TX, RX, $CTS)
+            where
+                TX: PinTx<$USART>,
+                RX: PinRx<$USART>,
+            {
+                const CTS: bool = true;
+            }
+        )+
+    };
+}
+
+flow_control_pins! {
+    Usart0: (rts: gpio::P16_0_8<Alternate<2>>, cts: gpio::P16_0_7<Alternate<2>>),
+    Usart1: (rts: gpio::P16_1_11<Alternate<2>>, cts: gpio::P16_1_10<Alternate<2>>),
+}
 
 
 /// Serial abstraction
@@ -39,24 +144,27 @@ where
     PINS: Pins<U>,
     U: Instance,
 {
-    pub fn new(usart: U, pins: PINS, config: Config) -> Self {
-        let pm = unsafe { &(*Pm::ptr()) };
-        U::enable_clock(&pm);
-
-        // TODO: Calculate correct baudrate divisor on the fly
+    pub fn new(usart: U, pins: PINS, config: Config, clocks: &rcc::Clocks) -> Result<Self, Error> {
+        let (rts, cts) = match config.flow_control {
+            FlowControl::None => (false, false),
+            FlowControl::Rts => (true, false),
+            FlowControl::Cts => (false, true),
+            FlowControl::RtsCts => (true, true),
+        };
+        if (rts && !PINS::RTS) || (cts && !PINS::CTS) {
+            return Err(Error::FlowControlPinMissing);
+        }
 
-        usart.divider().modify(|_, w| unsafe { w.brr().bits(0xd05) }); 
+        configure(&usart, &config, clocks)?;
 
-        // Enable tx / rx and reset USART
-        usart.control1().modify(|_, w| w
-            .te().enable()
-            .re().enable()
-            .ue().enable()
+        usart.control3().modify(|_, w| w
+            .rtse().bit(rts)
+            .ctse().bit(cts)
         );
 
-        while usart.flags().read().teack().bit_is_clear() {};
+        enable(&usart);
 
-        Serial { usart, pins }
+        Ok(Serial { usart, pins })
     }
 
     pub fn split(self) -> (Tx<U>, Rx<U>) {
@@ -75,8 +183,113 @@ where
     }
 }
 
-/// USART configuration
-pub struct Config {}
+impl<U, TX, RX, RTS, CTS> Serial<U, (TX, RX, RTS, CTS)>
+where
+    TX: PinTx<U>,
+    RX: PinRx<U>,
+    RTS: PinRts<U>,
+    CTS: PinCts<U>,
+    U: Instance,
+{
+    /// Creates a serial with RTS and CTS pins
+    ///
+    /// The enabled signals are selected by `config.flow_control`. RTS is deasserted while
+    /// the receive data register is full and the transmitter waits for CTS before each frame.
+    pub fn with_flow_control(
+        usart: U,
+        pins: (TX, RX),
+        rts: RTS,
+        cts: CTS,
+        config: Config,
+        clocks: &rcc::Clocks,
+    ) -> Result<Self, Error> {
+        Self::new(
+            usart,
+            (pins.0, pins.1, rts, cts),
+            config,
+            clocks,
+        )
+    }
+}
+
+/// Enables the bus clock and programs the baud rate and the frame format
+///
+/// The USART is left disabled, so the mode bits that require `UE = 0` can be set afterwards.
+fn configure<U: Instance>(usart: &U, config: &Config, clocks: &rcc::Clocks) -> Result<(), Error> {
+    let brr = baudrate_divisor(clocks.apb_p(), config.baudrate)?;
+    if config.wordlength == WordLength::DataBits9 && config.parity != Parity::ParityNone {
+        return Err(Error::WordLengthUnsupported);
+    }
+
+    // NOTE(unsafe) This executes only during initialisation
+    let pm = unsafe { &(*Pm::ptr()) };
+    U::enable(pm);
+
+    usart.control1().modify(|_, w| w.ue().disable());
+
+    usart.divider().modify(|_, w| unsafe { w.brr().bits(brr) });
+
+    usart.control2().modify(|_, w| {
+        match config.stopbits {
+            StopBits::STOP1 => w.stop_1()._1bit(),
+            StopBits::STOP2 => w.stop_1()._2bits(),
+        };
+        w.msbfirst().bit(config.msb_first)
+            .datainv().bit(config.invert_data)
+            .txinv().bit(config.invert_tx)
+            .rxinv().bit(config.invert_rx)
+    });
+
+    usart.control1().modify(|_, w| {
+        // The frame length programmed into `M` includes the parity bit
+        match (config.wordlength, config.parity) {
+            (WordLength::DataBits7, Parity::ParityNone) => w.m()._7bits(),
+            (WordLength::DataBits7, _) | (WordLength::DataBits8, Parity::ParityNone) => w.m()._8bits(),
+            _ => w.m()._9bits(),
+        };
+        match config.parity {
+            Parity::ParityNone => w.pce().disable(),
+            Parity::ParityEven => w.pce().enable().ps().parity(),
+            Parity::ParityOdd => w.pce().enable().ps().odd(),
+        }
+    });
+
+    Ok(())
+}
+
+/// Enables tx / rx, takes the USART out of reset and waits for the transmitter
+fn enable<U: Instance>(usart: &U) {
+    usart.control1().modify(|_, w| w
+        .te().enable()
+        .re().enable()
+        .ue().enable()
+    );
+
+    while usart.flags().read().teack().bit_is_clear() {};
+}
+
+/// Calculates the `BRR` value for the given input clock and baud rate
+///
+/// The USART baud rate is `clk / BRR`, where `BRR` must be at least 16.
+fn baudrate_divisor(clk: Hertz, baudrate: Bps) -> Result<u16, Error> {
+    if baudrate.0 == 0 {
+        return Err(Error::BaudrateUnreachable);
+    }
+
+    // Round to the nearest divisor
+    let brr = (clk.0 + baudrate.0 / 2) / baudrate.0;
+    if !(16..=u16::MAX as u32).contains(&brr) {
+        return Err(Error::BaudrateUnreachable);
+    }
+
+    let actual = clk.0 / brr;
+    let deviation = actual.abs_diff(baudrate.0) as u64 * 1000 / baudrate.0 as u64;
+    if deviation > BAUDRATE_TOLERANCE_PERMILLE as u64 {
+        return Err(Error::BaudrateUnreachable);
+    }
+
+    Ok(brr as u16)
+}
 
 /// Serial receiver
 pub struct Rx<U> {
@@ -90,44 +303,171 @@ where
     type Error = ErrorKind;
 }
 
-impl<U> Read<u8> for Rx<U>
+/// Receive errors latched together with a received word
+///
+/// The bits match the error flags in the `FLAGS` register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RxErrors(u8);
+
+impl RxErrors {
+    pub const PARITY: Self = Self(1 << 0);
+    pub const FRAMING: Self = Self(1 << 1);
+    pub const NOISE: Self = Self(1 << 2);
+    pub const OVERRUN: Self = Self(1 << 3);
+
+    const MASK: u32 = 0b1111;
+
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the most significant error as an `embedded-hal` error kind
+    pub fn kind(&self) -> ErrorKind {
+        if self.contains(Self::PARITY) {
+            ErrorKind::Parity
+        } else if self.contains(Self::FRAMING) {
+            ErrorKind::FrameFormat
+        } else if self.contains(Self::NOISE) {
+            ErrorKind::Noise
+        } else if self.contains(Self::OVERRUN) {
+            ErrorKind::Overrun
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+impl<U> Rx<U>
 where
     U: Instance,
 {
-    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+    /// Reads a received word together with the errors detected since the previous read
+    ///
+    /// The flags are sampled once and only the observed errors are cleared, so an error
+    /// raised by a frame arriving meanwhile is kept for the next call. Errors without a
+    /// received word are returned as `Err(Other)`.
+    pub fn read_with_status(&mut self) -> nb::Result<(u16, RxErrors), RxErrors> {
         // NOTE(unsafe) atomic read with no side effects
-        let flags = unsafe { (*U::ptr()).flags() };
+        let usart = unsafe { &*U::ptr() };
+        let flags = usart.flags().read();
 
-        if flags.read().pe().bit_is_set() {
-            flags.write(|w| w.pe().clear_bit_by_one());
-            return Err(nb::Error::Other(ErrorKind::Parity));
+        let errors = RxErrors((flags.bits() & RxErrors::MASK) as u8);
+        if !errors.is_empty() {
+            usart.flags().write(|w| unsafe { w.bits(errors.0 as u32) });
         }
-        if flags.read().fe().bit_is_set() {
-            flags.write(|w| w.fe().clear_bit_by_one());
-            return Err(nb::Error::Other(ErrorKind::FrameFormat));
+
+        if flags.rxne().bit_is_set() {
+            // The received parity bit is stored above the data bits
+            let word = usart.rxdata().read().rdr().bits() & data_mask::<U>();
+            Ok((word, errors))
+        } else if !errors.is_empty() {
+            Err(nb::Error::Other(errors))
+        } else {
+            Err(nb::Error::WouldBlock)
         }
-        if flags.read().nf().bit_is_set() {
-            flags.write(|w| w.nf().clear_bit_by_one());
-            return Err(nb::Error::Other(ErrorKind::Noise));
+    }
+
+    fn read_word(&mut self) -> nb::Result<u16, ErrorKind> {
+        match self.read_with_status() {
+            Ok((word, errors)) if errors.is_empty() => Ok(word),
+            Ok((_, errors)) | Err(nb::Error::Other(errors)) => Err(nb::Error::Other(errors.kind())),
+            Err(nb::Error::WouldBlock) => Err(nb::Error::WouldBlock),
         }
-        if flags.read().ore().bit_is_set() {
-            flags.write(|w| w.ore().clear_bit_by_one());
-            return Err(nb::Error::Other(ErrorKind::Overrun));
+    }
+
+    /// Returns `true` and clears the flag if the line went idle after a received frame
+    pub fn is_idle(&mut self) -> bool {
+        // NOTE(unsafe) the flag is only cleared through this receiver
+        let usart = unsafe { &*U::ptr() };
+        let idle = usart.flags().read().idle().bit_is_set();
+        if idle {
+            usart.flags().write(|w| w.idle().clear_bit_by_one());
         }
+        idle
+    }
 
-        if flags.read().rxne().bit_is_set() {
-            // NOTE(unsafe): Atomic read with no side effects
-            return Ok(unsafe {
-                // Casting to `u8` should be fine, as we've configured the USART
-                // to use 8 data bits.
-                (*U::ptr()).rxdata().read().rdr().bits() as u8
-            });
+    /// Returns `true` and clears the flag if a break was detected on the line
+    pub fn is_line_break(&mut self) -> bool {
+        // NOTE(unsafe) the flag is only cleared through this receiver
+        let usart = unsafe { &*U::ptr() };
+        let line_break = usart.flags().read().lbdf().bit_is_set();
+        if line_break {
+            usart.flags().write(|w| w.lbdf().clear_bit_by_one());
         }
+        line_break
+    }
+
+    /// Enables the idle line interrupt
+    pub fn listen_idle(&mut self) {
+        critical_section::with(|_| unsafe {
+            (*U::ptr()).control1().modify(|_, w| w.idleie().enable());
+        });
+    }
+
+    /// Disables the idle line interrupt
+    pub fn unlisten_idle(&mut self) {
+        critical_section::with(|_| unsafe {
+            (*U::ptr()).control1().modify(|_, w| w.idleie().disable());
+        });
+    }
+
+    /// Enables the line break interrupt
+    pub fn listen_line_break(&mut self) {
+        critical_section::with(|_| unsafe {
+            (*U::ptr()).control2().modify(|_, w| w.lbdie().enable());
+        });
+    }
 
-        Err(nb::Error::WouldBlock)
+    /// Disables the line break interrupt
+    pub fn unlisten_line_break(&mut self) {
+        critical_section::with(|_| unsafe {
+            (*U::ptr()).control2().modify(|_, w| w.lbdie().disable());
+        });
     }
 }
 
+impl<U> Read<u8> for Rx<U>
+where
+    U: Instance,
+{
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        // Casting to `u8` drops the 9th data bit, use `Read<u16>` for 9 bit words
+        self.read_word().map(|word| word as u8)
+    }
+}
+
+impl<U> Read<u16> for Rx<U>
+where
+    U: Instance,
+{
+    fn read(&mut self) -> nb::Result<u16, Self::Error> {
+        self.read_word()
+    }
+}
+
+/// Returns the mask of the data bits in `RXDATA` for the configured frame
+fn data_mask<U: Instance>() -> u16 {
+    // NOTE(unsafe) atomic read with no side effects
+    let control1 = unsafe { (*U::ptr()).control1().read() };
+
+    let frame_bits = match control1.m().bits() {
+        0b1_0000_0000_0000_0000 => 7,
+        0b1 => 9,
+        _ => 8,
+    };
+    let data_bits = frame_bits - control1.pce().bit_is_set() as u16;
+
+    (1 << data_bits) - 1
+}
+
 /// Serial transmitter
 pub struct Tx<U> {
     _usart: PhantomData<U>,
@@ -156,8 +496,57 @@ where
     }
 
     fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        Write::<u16>::write(self, byte as u16)
+    }
+}
+
+impl<U> Tx<U>
+where
+    U: Instance,
+{
+    /// Holds the line low for 13 bit times, which is longer than any frame
+    ///
+    /// Waits for the current frame to complete first.
+    pub fn send_break(&mut self) {
+        // NOTE(unsafe) the break request is only driven by this transmitter
+        let usart = unsafe { &*U::ptr() };
+
+        while usart.flags().read().tc().bit_is_clear() {}
+        usart.control3().modify(|_, w| w.sbkrq().break_tx());
+        riscv::asm::delay(13 * bit_cycles::<U>());
+        usart.control3().modify(|_, w| w.sbkrq().normal());
+    }
+}
+
+/// Returns the duration of one bit in core clock cycles
+///
+/// The core runs from the AHB clock, which is `DIV_APB_P + 1` times the USART clock.
+pub(crate) fn bit_cycles<U: Instance>() -> u32 {
+    // NOTE(unsafe) atomic reads with no side effects
+    let brr = unsafe { (*U::ptr()).divider().read().brr().bits() } as u32;
+    let apb_p_div = unsafe { (*Pm::ptr()).div_apb_p().read().bits() } + 1;
+
+    brr * apb_p_div
+}
+
+/// Converts `us` microseconds into core clock cycles
+///
+/// The core runs from the AHB clock, as in [`bit_cycles`].
+pub(crate) fn us_cycles(clocks: &rcc::Clocks, us: u32) -> u32 {
+    (us as u64 * clocks.ahb().0 as u64 / 1_000_000) as u32
+}
+
+impl<U> Write<u16> for Tx<U>
+where
+    U: Instance,
+{
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Write::<u8>::flush(self)
+    }
+
+    fn write(&mut self, word: u16) -> nb::Result<(), Self::Error> {
         unsafe {
-            (*U::ptr()).txdata().write(|w| w.tdr().bits(byte as u16));
+            (*U::ptr()).txdata().write(|w| w.tdr().bits(word));
             while (*U::ptr()).flags().read().tc().bit_is_clear() {};
         }
         Ok(())
@@ -165,23 +554,57 @@ where
 }
 
 /// Implemented by all USART instances
-pub trait Instance: Deref<Target = mik32v2_pac::usart_0::RegisterBlock> {
+pub trait Instance: Deref<Target = mik32v2_pac::usart_0::RegisterBlock> + rcc::Enable {
+    /// DMA request line of the USART
+    const DMA_REQUEST: crate::dma::Request;
+
     fn ptr() -> *const mik32v2_pac::usart_0::RegisterBlock;
-    fn enable_clock(pm: &mik32v2_pac::pm::RegisterBlock);
+    /// Unmasks the USART line in the EPIC
+    fn enable_interrupt_line();
+    /// Masks the USART line in the EPIC
+    fn disable_interrupt_line();
+    /// Clears the pending USART line in the EPIC
+    fn clear_interrupt_line();
+    fn buffered_state() -> &'static BufferedState;
+    fn async_state() -> &'static AsyncState;
 }
 
 macro_rules! impl_instance {
     ($(
-        $USARTX:ident: ($usartX:ident, $usartXen:ident),
+        $USARTX:ident: ($usartX:ident, $epic_line:ident, $request:ident),
     )+) => {
         $(
             impl Instance for $USARTX {
+                const DMA_REQUEST: crate::dma::Request = crate::dma::Request::$request;
+
                 fn ptr() -> *const mik32v2_pac::usart_0::RegisterBlock {
                     $USARTX::ptr()
                 }
 
-                fn enable_clock(pm: &mik32v2_pac::pm::RegisterBlock) {
-                    pm.clk_apb_p_set().modify(|_, w| w.$usartXen().set_bit());
+                fn enable_interrupt_line() {
+                    // NOTE(unsafe) atomic writes to stateless registers
+                    Epic::enable(unsafe { &*Pm::ptr() });
+                    unsafe { (*Epic::ptr()).mask_level_set().write(|w| w.$epic_line().set_bit()) };
+                }
+
+                fn disable_interrupt_line() {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    unsafe { (*Epic::ptr()).mask_level_clear().write(|w| w.$epic_line().set_bit()) };
+                }
+
+                fn clear_interrupt_line() {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    unsafe { (*Epic::ptr()).clear().write(|w| w.$epic_line().set_bit()) };
+                }
+
+                fn buffered_state() -> &'static BufferedState {
+                    static STATE: BufferedState = BufferedState::new();
+                    &STATE
+                }
+
+                fn async_state() -> &'static AsyncState {
+                    static STATE: AsyncState = AsyncState::new();
+                    &STATE
                 }
             }
         )+
@@ -189,8 +612,8 @@ macro_rules! impl_instance {
 }
 
 impl_instance! {
-    Usart0: (usart0, uart_0),
-    Usart1: (usart1, uart_1),
+    Usart0: (usart0, usart_0, Usart0),
+    Usart1: (usart1, usart_1, Usart1),
 }
 
 impl<U> fmt::Write for Tx<U>
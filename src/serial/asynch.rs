@@ -0,0 +1,281 @@
+//! Async serial on `embedded-io-async`
+//!
+//! The futures enable the USART interrupt they wait for and register their waker. The
+//! interrupt handler [`on_interrupt`] masks the interrupt sources that fired and wakes the
+//! waiting task, which then reads the flags itself. [`on_interrupt`] must be called from
+//! the trap handler whenever the EPIC reports the USART line.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::{Context, Poll, Waker};
+
+use critical_section::Mutex;
+use embedded_hal_nb::serial::ErrorKind;
+
+use super::{Instance, Pins, Rx, RxErrors, Serial};
+
+/// Receive error of [`AsyncSerial`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error(pub RxErrors);
+
+impl embedded_io::Error for Error {
+    /// Corrupted frames are `InvalidData`, an overrun loses bytes but not their content
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self.0.kind() {
+            ErrorKind::Parity | ErrorKind::FrameFormat | ErrorKind::Noise => {
+                embedded_io::ErrorKind::InvalidData
+            }
+            _ => embedded_io::ErrorKind::Other,
+        }
+    }
+}
+
+/// Per instance wakers shared between [`AsyncSerial`] and the interrupt handler
+pub struct AsyncState {
+    rx_waker: Mutex<RefCell<Option<Waker>>>,
+    tx_waker: Mutex<RefCell<Option<Waker>>>,
+}
+
+impl AsyncState {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx_waker: Mutex::new(RefCell::new(None)),
+            tx_waker: Mutex::new(RefCell::new(None)),
+        }
+    }
+}
+
+fn register(slot: &Mutex<RefCell<Option<Waker>>>, waker: &Waker) {
+    critical_section::with(|cs| {
+        let mut slot = slot.borrow_ref_mut(cs);
+        match slot.as_ref() {
+            Some(registered) if registered.will_wake(waker) => {}
+            _ => *slot = Some(waker.clone()),
+        }
+    });
+}
+
+fn wake(slot: &Mutex<RefCell<Option<Waker>>>) {
+    if let Some(waker) = critical_section::with(|cs| slot.borrow_ref_mut(cs).take()) {
+        waker.wake();
+    }
+}
+
+/// Handles the USART interrupt for an [`AsyncSerial`] on `U`
+pub fn on_interrupt<U: Instance>() {
+    // NOTE(unsafe) only called from the interrupt handler
+    let usart = unsafe { &*U::ptr() };
+    let state = U::async_state();
+    let flags = usart.flags().read();
+    let control1 = usart.control1().read();
+
+    let rx_event = (control1.rxneie().bit_is_set()
+        && (flags.rxne().bit_is_set() || flags.ore().bit_is_set()))
+        || (control1.idleie().bit_is_set() && flags.idle().bit_is_set());
+    if rx_event {
+        usart.control1().modify(|_, w| w.rxneie().disable().idleie().disable());
+        wake(&state.rx_waker);
+    }
+
+    let tx_event = (control1.txeie().bit_is_set() && flags.txe().bit_is_set())
+        || (control1.tcie().bit_is_set() && flags.tc().bit_is_set());
+    if tx_event {
+        usart.control1().modify(|_, w| w.txeie().disable().tcie().disable());
+        wake(&state.tx_waker);
+    }
+
+    U::clear_interrupt_line();
+}
+
+/// Receive interrupts enabled by a pending read
+///
+/// Dropping it disables `RXNEIE` and `IDLEIE` and unregisters the RX waker, so a cancelled
+/// read leaves no interrupt enabled.
+struct RxGuard<U: Instance> {
+    _usart: PhantomData<U>,
+}
+
+impl<U: Instance> Drop for RxGuard<U> {
+    fn drop(&mut self) {
+        // NOTE(unsafe) the receive interrupt enables are owned by the pending read
+        let usart = unsafe { &*U::ptr() };
+        critical_section::with(|cs| {
+            usart.control1().modify(|_, w| w.rxneie().disable().idleie().disable());
+            U::async_state().rx_waker.borrow_ref_mut(cs).take();
+        });
+    }
+}
+
+/// Serial driven by futures
+pub struct AsyncSerial<U, PINS> {
+    serial: Serial<U, PINS>,
+    rx: Rx<U>,
+    /// Error seen after bytes were already returned, reported by the next read
+    pending_error: Option<Error>,
+}
+
+impl<U, PINS> AsyncSerial<U, PINS>
+where
+    PINS: Pins<U>,
+    U: Instance,
+{
+    pub fn new(serial: Serial<U, PINS>) -> Self {
+        U::enable_interrupt_line();
+        Self {
+            serial,
+            rx: Rx { _usart: PhantomData },
+            pending_error: None,
+        }
+    }
+
+    pub fn release(self) -> Serial<U, PINS> {
+        critical_section::with(|_| {
+            self.serial.usart.control1().modify(|_, w| w
+                .rxneie().disable()
+                .idleie().disable()
+                .txeie().disable()
+                .tcie().disable()
+            );
+        });
+        U::disable_interrupt_line();
+        self.serial
+    }
+
+    /// Registers the RX waker and enables the receive interrupts
+    fn listen_rx(&self, cx: &mut Context<'_>, idle: bool) {
+        register(&U::async_state().rx_waker, cx.waker());
+        critical_section::with(|_| {
+            self.serial.usart.control1().modify(|_, w| {
+                if idle {
+                    w.idleie().enable();
+                }
+                w.rxneie().enable()
+            });
+        });
+    }
+
+    /// Moves the received bytes into `buf` until it is full or no byte is left
+    ///
+    /// An error after `n > 0` bytes ends the drain without failing, it is kept for the
+    /// next read so the bytes received before it are not lost.
+    fn drain(&mut self, buf: &mut [u8], n: &mut usize) -> Result<(), Error> {
+        while *n < buf.len() {
+            match self.rx.read_with_status() {
+                Ok((word, errors)) if errors.is_empty() => buf[*n] = word as u8,
+                Ok((_, errors)) | Err(nb::Error::Other(errors)) if *n > 0 => {
+                    self.pending_error = Some(Error(errors));
+                    break;
+                }
+                Ok((_, errors)) | Err(nb::Error::Other(errors)) => return Err(Error(errors)),
+                Err(nb::Error::WouldBlock) => break,
+            }
+            *n += 1;
+        }
+        Ok(())
+    }
+
+    /// Receives into `buf` until the line goes idle after a frame or `buf` is full
+    ///
+    /// Returns the number of received bytes, which is at least one.
+    ///
+    /// The reference manual describes `IDLE` as set after 8 idle bit times "with `RXNE`
+    /// set". This relies on the reading where a received frame arms the idle detection,
+    /// as on the STM32 USART this block follows, so `IDLE` is still raised after the
+    /// bytes were moved out of `RDR`. It has not been verified on silicon; if `IDLE` needs
+    /// an unread byte, the read only completes once `buf` is full.
+    pub async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(error) = self.pending_error.take() {
+            return Err(error);
+        }
+
+        // An idle flag without pending data belongs to an earlier transfer
+        if self.serial.usart.flags().read().rxne().bit_is_clear() {
+            self.rx.is_idle();
+        }
+
+        let _guard = RxGuard::<U> { _usart: PhantomData };
+        let mut n = 0;
+        poll_fn(|cx| {
+            self.drain(buf, &mut n)?;
+            if n == buf.len() || (n > 0 && (self.pending_error.is_some() || self.rx.is_idle())) {
+                return Poll::Ready(Ok(n));
+            }
+            self.listen_rx(cx, true);
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<U, PINS> embedded_io::ErrorType for AsyncSerial<U, PINS> {
+    type Error = Error;
+}
+
+impl<U, PINS> embedded_io_async::Read for AsyncSerial<U, PINS>
+where
+    PINS: Pins<U>,
+    U: Instance,
+{
+    /// Returns the received bytes, waiting only while none is available
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(error) = self.pending_error.take() {
+            return Err(error);
+        }
+
+        let _guard = RxGuard::<U> { _usart: PhantomData };
+        let mut n = 0;
+        poll_fn(|cx| {
+            self.drain(buf, &mut n)?;
+            if n > 0 {
+                return Poll::Ready(Ok(n));
+            }
+            self.listen_rx(cx, false);
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<U, PINS> embedded_io_async::Write for AsyncSerial<U, PINS>
+where
+    PINS: Pins<U>,
+    U: Instance,
+{
+    /// Queues the whole buffer, waiting on the TXE interrupt between bytes
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let usart = &self.serial.usart;
+        for &byte in buf {
+            poll_fn(|cx| {
+                if usart.flags().read().txe().bit_is_set() {
+                    return Poll::Ready(());
+                }
+                register(&U::async_state().tx_waker, cx.waker());
+                critical_section::with(|_| usart.control1().modify(|_, w| w.txeie().enable()));
+                Poll::Pending
+            })
+            .await;
+            usart.txdata().write(|w| unsafe { w.tdr().bits(byte as u16) });
+        }
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        let usart = &self.serial.usart;
+        poll_fn(|cx| {
+            if usart.flags().read().tc().bit_is_set() {
+                return Poll::Ready(Ok(()));
+            }
+            register(&U::async_state().tx_waker, cx.waker());
+            critical_section::with(|_| usart.control1().modify(|_, w| w.tcie().enable()));
+            Poll::Pending
+        })
+        .await
+    }
+}
@@ -0,0 +1,284 @@
+//! Interrupt driven serial with ring buffers
+//!
+//! The USART interrupt moves received bytes into the RX ring buffer and feeds the
+//! transmitter from the TX ring buffer, so the application only touches memory.
+//! [`on_interrupt`] must be called from the trap handler whenever the EPIC reports the
+//! USART line.
+
+use core::convert::Infallible;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+
+use super::{Instance, Pins, Serial};
+
+/// Single producer, single consumer byte queue over a user supplied buffer
+///
+/// Only atomic loads and stores are used, since the core has no atomic read-modify-write
+/// instructions. One slot is kept free to tell a full buffer from an empty one.
+struct RingBuffer {
+    buf: AtomicPtr<u8>,
+    len: AtomicUsize,
+    start: AtomicUsize,
+    end: AtomicUsize,
+}
+
+impl RingBuffer {
+    const fn new() -> Self {
+        Self {
+            buf: AtomicPtr::new(ptr::null_mut()),
+            len: AtomicUsize::new(0),
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Must not be called while the producer or the consumer uses the buffer.
+    unsafe fn init(&self, buf: &'static mut [u8]) {
+        self.start.store(0, Ordering::Relaxed);
+        self.end.store(0, Ordering::Relaxed);
+        self.len.store(buf.len(), Ordering::Relaxed);
+        self.buf.store(buf.as_mut_ptr(), Ordering::Release);
+    }
+
+    fn wrap(&self, i: usize) -> usize {
+        let len = self.len.load(Ordering::Relaxed);
+        if i + 1 == len { 0 } else { i + 1 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.start.load(Ordering::Acquire) == self.end.load(Ordering::Acquire)
+    }
+
+    fn is_full(&self) -> bool {
+        self.len.load(Ordering::Relaxed) == 0
+            || self.wrap(self.end.load(Ordering::Acquire)) == self.start.load(Ordering::Acquire)
+    }
+
+    /// Called by the producer only
+    fn push(&self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let end = self.end.load(Ordering::Relaxed);
+        // NOTE(unsafe) the slot at `end` is owned by the producer until `end` moves past it
+        unsafe { self.buf.load(Ordering::Acquire).add(end).write_volatile(byte) };
+        self.end.store(self.wrap(end), Ordering::Release);
+        true
+    }
+
+    /// Called by the consumer only
+    fn pop(&self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let start = self.start.load(Ordering::Relaxed);
+        // NOTE(unsafe) the slot at `start` is owned by the consumer until `start` moves past it
+        let byte = unsafe { self.buf.load(Ordering::Acquire).add(start).read_volatile() };
+        self.start.store(self.wrap(start), Ordering::Release);
+        Some(byte)
+    }
+}
+
+/// Per instance state shared between [`BufferedSerial`] and the interrupt handler
+pub struct BufferedState {
+    rx: RingBuffer,
+    tx: RingBuffer,
+    rx_overflows: AtomicU32,
+    overruns: AtomicU32,
+}
+
+impl BufferedState {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            rx_overflows: AtomicU32::new(0),
+            overruns: AtomicU32::new(0),
+        }
+    }
+}
+
+/// Increments a counter written only by the interrupt handler
+fn increment(counter: &AtomicU32) {
+    counter.store(counter.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+}
+
+/// Interrupt driven serial
+pub struct BufferedSerial<U, PINS> {
+    serial: Serial<U, PINS>,
+}
+
+impl<U, PINS> BufferedSerial<U, PINS>
+where
+    PINS: Pins<U>,
+    U: Instance,
+{
+    /// Takes over `serial`, queueing received and transmitted bytes in `rx_buf` and `tx_buf`
+    ///
+    /// One slot of each ring buffer is kept free, so a buffer holds at most `len - 1` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rx_buf` or `tx_buf` is shorter than 2 bytes.
+    pub fn new(serial: Serial<U, PINS>, rx_buf: &'static mut [u8], tx_buf: &'static mut [u8]) -> Self {
+        assert!(rx_buf.len() >= 2, "the RX buffer must hold at least 2 bytes");
+        assert!(tx_buf.len() >= 2, "the TX buffer must hold at least 2 bytes");
+        let state = U::buffered_state();
+
+        // NOTE(unsafe) the USART interrupt is not enabled yet
+        unsafe {
+            state.rx.init(rx_buf);
+            state.tx.init(tx_buf);
+        }
+        state.rx_overflows.store(0, Ordering::Relaxed);
+        state.overruns.store(0, Ordering::Relaxed);
+
+        serial.usart.control1().modify(|_, w| w.rxneie().enable());
+        serial.usart.control3().modify(|_, w| w.eie().enable());
+        U::enable_interrupt_line();
+
+        Self { serial }
+    }
+
+    /// Number of received bytes dropped because the RX ring buffer was full
+    pub fn rx_overflows(&self) -> u32 {
+        U::buffered_state().rx_overflows.load(Ordering::Relaxed)
+    }
+
+    /// Number of bytes lost in hardware because the interrupt was serviced too late
+    pub fn overruns(&self) -> u32 {
+        U::buffered_state().overruns.load(Ordering::Relaxed)
+    }
+
+    pub fn release(self) -> Serial<U, PINS> {
+        self.serial.usart.control1().modify(|_, w| w.rxneie().disable().txeie().disable());
+        self.serial.usart.control3().modify(|_, w| w.eie().disable());
+        U::disable_interrupt_line();
+        self.serial
+    }
+}
+
+/// Handles the USART interrupt for a [`BufferedSerial`] on `U`
+pub fn on_interrupt<U: Instance>() {
+    // NOTE(unsafe) only called from the interrupt handler
+    let usart = unsafe { &*U::ptr() };
+    let state = U::buffered_state();
+    let flags = usart.flags().read();
+
+    if flags.ore().bit_is_set() {
+        usart.flags().write(|w| w.ore().clear_bit_by_one());
+        increment(&state.overruns);
+    }
+    if flags.pe().bit_is_set() || flags.fe().bit_is_set() || flags.nf().bit_is_set() {
+        usart.flags().write(|w| w
+            .pe().clear_bit_by_one()
+            .fe().clear_bit_by_one()
+            .nf().clear_bit_by_one()
+        );
+    }
+
+    if flags.rxne().bit_is_set() {
+        let byte = usart.rxdata().read().rdr().bits() as u8;
+        if !state.rx.push(byte) {
+            increment(&state.rx_overflows);
+        }
+    }
+
+    if flags.txe().bit_is_set() && usart.control1().read().txeie().bit_is_set() {
+        match state.tx.pop() {
+            Some(byte) => {
+                usart.txdata().write(|w| unsafe { w.tdr().bits(byte as u16) });
+            }
+            None => {
+                usart.control1().modify(|_, w| w.txeie().disable());
+            }
+        }
+    }
+
+    U::clear_interrupt_line();
+}
+
+impl<U, PINS> embedded_io::ErrorType for BufferedSerial<U, PINS> {
+    type Error = Infallible;
+}
+
+impl<U, PINS> embedded_io::ReadReady for BufferedSerial<U, PINS>
+where
+    PINS: Pins<U>,
+    U: Instance,
+{
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(!U::buffered_state().rx.is_empty())
+    }
+}
+
+impl<U, PINS> embedded_io::Read for BufferedSerial<U, PINS>
+where
+    PINS: Pins<U>,
+    U: Instance,
+{
+    /// Returns the buffered bytes, waiting only while none is available
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let rx = &U::buffered_state().rx;
+        while rx.is_empty() {}
+
+        let mut n = 0;
+        while n < buf.len() {
+            match rx.pop() {
+                Some(byte) => buf[n] = byte,
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+impl<U, PINS> embedded_io::WriteReady for BufferedSerial<U, PINS>
+where
+    PINS: Pins<U>,
+    U: Instance,
+{
+    fn write_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(!U::buffered_state().tx.is_full())
+    }
+}
+
+impl<U, PINS> embedded_io::Write for BufferedSerial<U, PINS>
+where
+    PINS: Pins<U>,
+    U: Instance,
+{
+    /// Queues as many bytes as fit, waiting only while the TX ring buffer is full
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let tx = &U::buffered_state().tx;
+        while tx.is_full() {}
+
+        let mut n = 0;
+        while n < buf.len() && tx.push(buf[n]) {
+            n += 1;
+        }
+
+        // The interrupt handler masks TXEIE once the buffer runs empty
+        critical_section::with(|_| {
+            self.serial.usart.control1().modify(|_, w| w.txeie().enable());
+        });
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        while !U::buffered_state().tx.is_empty() {}
+        while self.serial.usart.flags().read().tc().bit_is_clear() {}
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use crate::time::Bps;
+
+/// Number of data bits in a frame, the parity bit is not included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordLength {
+    DataBits7,
+    DataBits8,
+    DataBits9,
+}
+
+/// Parity bit generation and checking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    ParityNone,
+    ParityEven,
+    ParityOdd,
+}
+
+/// Number of stop bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    /// 1 stop bit
+    STOP1,
+    /// 2 stop bits
+    STOP2,
+}
+
+/// Hardware flow control
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// The receiver drives RTS
+    Rts,
+    /// The transmitter waits for CTS
+    Cts,
+    RtsCts,
+}
+
+/// USART configuration
+pub struct Config {
+    /// Baud rate
+    pub baudrate: Bps,
+    pub wordlength: WordLength,
+    pub parity: Parity,
+    pub stopbits: StopBits,
+    /// Transmit and receive the most significant bit first
+    pub msb_first: bool,
+    /// Invert the data bits (1 is sent as low level)
+    pub invert_data: bool,
+    /// Invert the level of the TX output, idle becomes low
+    pub invert_tx: bool,
+    /// Invert the level of the RX input, idle becomes low
+    pub invert_rx: bool,
+    /// Signals used for flow control, the only place they are selected
+    ///
+    /// Requires the matching RTS and/or CTS pins in the pin tuple: `(TX, RX, RTS)`,
+    /// `(TX, RX, CTS)` or `(TX, RX, RTS, CTS)`.
+    pub flow_control: FlowControl,
+}
+
+impl Config {
+    pub fn baudrate(mut self, baudrate: Bps) -> Self {
+        self.baudrate = baudrate;
+        self
+    }
+
+    pub fn wordlength(mut self, wordlength: WordLength) -> Self {
+        self.wordlength = wordlength;
+        self
+    }
+
+    pub fn wordlength_7(self) -> Self {
+        self.wordlength(WordLength::DataBits7)
+    }
+
+    pub fn wordlength_8(self) -> Self {
+        self.wordlength(WordLength::DataBits8)
+    }
+
+    pub fn wordlength_9(self) -> Self {
+        self.wordlength(WordLength::DataBits9)
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn parity_none(self) -> Self {
+        self.parity(Parity::ParityNone)
+    }
+
+    pub fn parity_even(self) -> Self {
+        self.parity(Parity::ParityEven)
+    }
+
+    pub fn parity_odd(self) -> Self {
+        self.parity(Parity::ParityOdd)
+    }
+
+    pub fn stopbits(mut self, stopbits: StopBits) -> Self {
+        self.stopbits = stopbits;
+        self
+    }
+
+    pub fn msb_first(mut self, msb_first: bool) -> Self {
+        self.msb_first = msb_first;
+        self
+    }
+
+    pub fn invert_data(mut self, invert: bool) -> Self {
+        self.invert_data = invert;
+        self
+    }
+
+    pub fn invert_tx(mut self, invert: bool) -> Self {
+        self.invert_tx = invert;
+        self
+    }
+
+    pub fn invert_rx(mut self, invert: bool) -> Self {
+        self.invert_rx = invert;
+        self
+    }
+
+    pub fn flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            baudrate: Bps(115_200),
+            wordlength: WordLength::DataBits8,
+            parity: Parity::ParityNone,
+            stopbits: StopBits::STOP1,
+            msb_first: false,
+            invert_data: false,
+            invert_tx: false,
+            invert_rx: false,
+            flow_control: FlowControl::None,
+        }
+    }
+}
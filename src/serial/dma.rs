@@ -0,0 +1,224 @@
+//! DMA transfers for the serial transmitter and receiver
+
+use core::mem::ManuallyDrop;
+use core::ptr;
+
+use super::{Instance, Rx, Tx};
+use crate::dma::{self, Channel, Endpoint, Priority, TransferConfig};
+
+/// Data was overwritten by the DMA before it was read from the circular buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overrun;
+
+/// Running DMA transmission
+///
+/// Dropping it before it is done aborts the transmission and stops the channel.
+pub struct TxTransfer<U: Instance, CH: Channel> {
+    tx: Tx<U>,
+    channel: CH,
+    buf: &'static [u8],
+}
+
+impl<U> Tx<U>
+where
+    U: Instance,
+{
+    /// Transmits `buf` through `channel` without involving the CPU
+    ///
+    /// An empty `buf` returns a transfer that is already done.
+    pub fn write_dma<CH: Channel>(self, buf: &'static [u8], mut channel: CH) -> TxTransfer<U, CH> {
+        if buf.is_empty() {
+            return TxTransfer { tx: self, channel, buf };
+        }
+
+        // NOTE(unsafe) the DMA enable is only driven by this transmitter
+        let usart = unsafe { &*U::ptr() };
+        usart.control3().modify(|_, w| w.dmat().enable());
+
+        let config = TransferConfig {
+            read: Endpoint::Memory,
+            write: Endpoint::Peripheral(U::DMA_REQUEST),
+            priority: Priority::Low,
+        };
+        // NOTE(unsafe) `buf` is static and the transfer owns the channel
+        unsafe {
+            channel.start(buf.as_ptr() as u32, usart.txdata().as_ptr() as u32, buf.len(), config);
+        }
+
+        TxTransfer { tx: self, channel, buf }
+    }
+}
+
+impl<U, CH> TxTransfer<U, CH>
+where
+    U: Instance,
+    CH: Channel,
+{
+    /// Returns `true` once the last byte left the shift register
+    pub fn is_done(&self) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        self.buf.is_empty()
+            || (!self.channel.is_busy() && unsafe { (*U::ptr()).flags().read().tc().bit_is_set() })
+    }
+
+    /// Waits for the transmission to complete and returns the resources
+    pub fn wait(mut self) -> (Tx<U>, CH, &'static [u8]) {
+        while !self.is_done() {}
+        self.release();
+
+        let this = ManuallyDrop::new(self);
+        // NOTE(unsafe) the fields are moved out once and `this` is never dropped
+        unsafe { (ptr::read(&this.tx), ptr::read(&this.channel), this.buf) }
+    }
+
+    /// Stops the channel and the DMA requests of the transmitter
+    fn release(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        self.channel.stop();
+        // NOTE(unsafe) the DMA enable is only driven by this transmitter
+        unsafe { (*U::ptr()).control3().modify(|_, w| w.dmat().disable()) };
+    }
+}
+
+impl<U, CH> Drop for TxTransfer<U, CH>
+where
+    U: Instance,
+    CH: Channel,
+{
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Reception into a circular buffer
+///
+/// The controller has no circular mode, the channel is restarted from the beginning of the
+/// buffer by [`dma::on_interrupt`] each time it is full. Until the interrupt handler ran,
+/// no DMA request is served: bytes arriving in this gap stay in the receive data register,
+/// and a second byte overruns the USART. The interrupt latency has to stay below one frame
+/// time at the used baud rate.
+///
+/// Dropping it stops the reception like [`RxCircular::stop`].
+pub struct RxCircular<U: Instance, CH: Channel> {
+    rx: Rx<U>,
+    channel: CH,
+    buf: &'static mut [u8],
+    /// Number of bytes consumed since the start, wrapping
+    read: u32,
+    /// Position of the next byte to read in `buf`
+    index: usize,
+}
+
+impl<U> Rx<U>
+where
+    U: Instance,
+{
+    /// Continuously receives into `buf` through `channel`
+    ///
+    /// Nothing is received into an empty `buf`.
+    pub fn read_dma<CH: Channel>(self, buf: &'static mut [u8], mut channel: CH) -> RxCircular<U, CH> {
+        if buf.is_empty() {
+            return RxCircular { rx: self, channel, buf, read: 0, index: 0 };
+        }
+
+        // NOTE(unsafe) the DMA enable is only driven by this receiver
+        let usart = unsafe { &*U::ptr() };
+        usart.control3().modify(|_, w| w.dmar().enable());
+
+        let config = TransferConfig {
+            read: Endpoint::Peripheral(U::DMA_REQUEST),
+            write: Endpoint::Memory,
+            priority: Priority::High,
+        };
+        // NOTE(unsafe) `buf` is static and only read through volatile reads until released
+        unsafe {
+            channel.start_circular(usart.rxdata().as_ptr() as u32, buf.as_mut_ptr() as u32, buf.len(), config);
+        }
+
+        RxCircular { rx: self, channel, buf, read: 0, index: 0 }
+    }
+}
+
+impl<U, CH> RxCircular<U, CH>
+where
+    U: Instance,
+    CH: Channel,
+{
+    /// Returns the total number of bytes written by the DMA, wrapping, and the position
+    /// of the next write in `buf`
+    fn written(&self) -> (u32, usize) {
+        let len = self.buf.len();
+        if len == 0 {
+            return (0, 0);
+        }
+        loop {
+            let laps = dma::circular_laps(CH::INDEX);
+            let transferred = self.channel.transferred().min(len);
+            // The interrupt may restart the channel between the two reads
+            if dma::circular_laps(CH::INDEX) == laps {
+                let total = laps.wrapping_mul(len as u32).wrapping_add(transferred as u32);
+                return (total, transferred % len);
+            }
+        }
+    }
+
+    /// Number of received bytes not read yet
+    pub fn available(&self) -> usize {
+        self.written().0.wrapping_sub(self.read) as usize
+    }
+
+    /// Copies the received bytes into `out` and returns their number
+    ///
+    /// Returns `Err(Overrun)` and drops the pending bytes if the DMA wrapped around past
+    /// unread data.
+    pub fn read(&mut self, out: &mut [u8]) -> Result<usize, Overrun> {
+        let (written, position) = self.written();
+        let available = written.wrapping_sub(self.read) as usize;
+        if available > self.buf.len() {
+            self.read = written;
+            self.index = position;
+            return Err(Overrun);
+        }
+
+        let n = available.min(out.len());
+        for byte in &mut out[..n] {
+            // NOTE(unsafe) the slot was written by the DMA and is not written again before
+            // the buffer wraps around
+            *byte = unsafe { ptr::read_volatile(self.buf.as_ptr().add(self.index)) };
+            self.index = if self.index + 1 == self.buf.len() { 0 } else { self.index + 1 };
+        }
+        self.read = self.read.wrapping_add(n as u32);
+        Ok(n)
+    }
+
+    /// Stops the reception and returns the resources
+    pub fn stop(mut self) -> (Rx<U>, CH, &'static mut [u8]) {
+        self.release();
+
+        let this = ManuallyDrop::new(self);
+        // NOTE(unsafe) the fields are moved out once and `this` is never dropped
+        unsafe { (ptr::read(&this.rx), ptr::read(&this.channel), ptr::read(&this.buf)) }
+    }
+
+    /// Stops the channel and the DMA requests of the receiver
+    fn release(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        self.channel.stop();
+        // NOTE(unsafe) the DMA enable is only driven by this receiver
+        unsafe { (*U::ptr()).control3().modify(|_, w| w.dmar().disable()) };
+    }
+}
+
+impl<U, CH> Drop for RxCircular<U, CH>
+where
+    U: Instance,
+    CH: Channel,
+{
+    fn drop(&mut self) {
+        self.release();
+    }
+}
@@ -0,0 +1,138 @@
+//! Single-wire half-duplex
+//!
+//! With `HDSEL` set, the receiver listens on the TX pin and the RX pin is not used. The
+//! line is shared, so the receiver is switched off while a frame is sent to keep the
+//! transmitted words out of `RXDATA`.
+
+use core::marker::PhantomData;
+
+use embedded_hal_nb::serial::{ErrorKind, ErrorType, Read, Write};
+
+use super::{configure, enable, Config, Error, FlowControl, Instance, PinTx, Rx, RxErrors, Serial, Tx};
+use crate::rcc;
+
+/// Serial sharing one wire between the transmitter and the receiver
+pub struct HalfDuplex<U, TX> {
+    usart: U,
+    pin: TX,
+    rx: Rx<U>,
+    tx: Tx<U>,
+}
+
+impl<U, TX> Serial<U, TX>
+where
+    TX: PinTx<U>,
+    U: Instance,
+{
+    /// Creates a half-duplex serial on the TX pin of `usart`
+    ///
+    /// The TX pin has to be pulled up, either externally or by the pad configuration,
+    /// since the line is only driven while transmitting. Flow control is not available.
+    pub fn half_duplex(
+        usart: U,
+        pin: TX,
+        config: Config,
+        clocks: &rcc::Clocks,
+    ) -> Result<HalfDuplex<U, TX>, Error> {
+        if config.flow_control != FlowControl::None {
+            return Err(Error::FlowControlPinMissing);
+        }
+
+        configure(&usart, &config, clocks)?;
+
+        // HDSEL may only be written while the USART is disabled
+        usart.control3().modify(|_, w| w
+            .rtse().clear_bit()
+            .ctse().clear_bit()
+            .hdsel().half_duplex()
+        );
+
+        enable(&usart);
+
+        Ok(HalfDuplex {
+            usart,
+            pin,
+            rx: Rx { _usart: PhantomData },
+            tx: Tx { _usart: PhantomData },
+        })
+    }
+}
+
+impl<U, TX> HalfDuplex<U, TX>
+where
+    U: Instance,
+{
+    /// Reads a received word together with the receive errors, see [`Rx::read_with_status`]
+    pub fn read_with_status(&mut self) -> nb::Result<(u16, RxErrors), RxErrors> {
+        self.rx.read_with_status()
+    }
+
+    /// Sends one word with the receiver switched off
+    ///
+    /// Returns once the stop bits are out, so the line is free for the answer.
+    fn write_word(&mut self, word: u16) {
+        self.usart.control1().modify(|_, w| w.re().disable());
+        let _ = Write::<u16>::write(&mut self.tx, word);
+        self.usart.control1().modify(|_, w| w.re().enable());
+    }
+
+    /// Leaves half-duplex mode and returns the peripheral and the pin
+    pub fn release(self) -> (U, TX) {
+        self.usart.control1().modify(|_, w| w.ue().disable());
+        self.usart.control3().modify(|_, w| w.hdsel().duplex());
+        (self.usart, self.pin)
+    }
+}
+
+impl<U, TX> ErrorType for HalfDuplex<U, TX>
+where
+    U: Instance,
+{
+    type Error = ErrorKind;
+}
+
+impl<U, TX> Read<u8> for HalfDuplex<U, TX>
+where
+    U: Instance,
+{
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        Read::<u8>::read(&mut self.rx)
+    }
+}
+
+impl<U, TX> Read<u16> for HalfDuplex<U, TX>
+where
+    U: Instance,
+{
+    fn read(&mut self) -> nb::Result<u16, Self::Error> {
+        Read::<u16>::read(&mut self.rx)
+    }
+}
+
+impl<U, TX> Write<u8> for HalfDuplex<U, TX>
+where
+    U: Instance,
+{
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Write::<u8>::flush(&mut self.tx)
+    }
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.write_word(byte as u16);
+        Ok(())
+    }
+}
+
+impl<U, TX> Write<u16> for HalfDuplex<U, TX>
+where
+    U: Instance,
+{
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Write::<u16>::flush(&mut self.tx)
+    }
+
+    fn write(&mut self, word: u16) -> nb::Result<(), Self::Error> {
+        self.write_word(word);
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+//! LIN 2.x master and slave
+//!
+//! The break is generated with the break request of the transmitter and detected through
+//! the line break flag, everything else is plain 8N1 traffic. A LIN transceiver echoes
+//! every transmitted byte back to RX, the echo is compared with the sent byte to detect
+//! bus collisions.
+//!
+//! Frame timeouts follow the LIN 2.x `T_Header_Maximum` and `T_Response_Maximum` limits
+//! and are measured with a [`Timeout`], e.g. a [`TimerTimeout`] on one of the `Timer16_x`.
+
+use core::marker::PhantomData;
+
+use mik32v2_pac::{Pm, Timer16_0, Timer16_1, Timer16_2};
+
+use super::{Instance, Pins, Rx, RxErrors, Serial, Tx};
+use crate::rcc::{self, Enable};
+use crate::time::Hertz;
+
+/// Value of the sync byte following the break
+const SYNC: u8 = 0x55;
+
+/// LIN error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The header or the response was not complete in time
+    Timeout,
+    /// The byte after the break was not the sync byte
+    Sync,
+    /// The parity bits of the protected identifier do not match
+    IdParity,
+    /// The checksum byte does not match the received data
+    Checksum,
+    /// A transmitted byte was read back differently from the bus
+    BitError,
+    /// A byte was received with a parity, framing, noise or overrun error
+    Receive(RxErrors),
+}
+
+/// Checksum model of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumModel {
+    /// LIN 1.x checksum over the data bytes only
+    Classic,
+    /// LIN 2.x checksum over the protected identifier and the data bytes
+    ///
+    /// The diagnostic frames `0x3C` and `0x3D` always use the classic checksum.
+    Enhanced,
+}
+
+/// Returns the protected identifier, the 6 bit `id` with its two parity bits
+pub const fn protected_id(id: u8) -> u8 {
+    let id = id & 0x3F;
+    let bit = |n: u8| (id >> n) & 1;
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+    id | p0 << 6 | p1 << 7
+}
+
+/// Computes the checksum byte of a frame with the protected identifier `pid`
+pub fn checksum(model: ChecksumModel, pid: u8, data: &[u8]) -> u8 {
+    let id = pid & 0x3F;
+    let mut sum: u16 = match model {
+        ChecksumModel::Enhanced if id != 0x3C && id != 0x3D => pid as u16,
+        _ => 0,
+    };
+    for &byte in data {
+        // Sum with the carry added back in
+        sum += byte as u16;
+        if sum > 0xFF {
+            sum -= 0xFF;
+        }
+    }
+    !(sum as u8)
+}
+
+/// A one-shot timeout
+pub trait Timeout {
+    /// Starts a timeout of `us` microseconds, replacing a running one
+    fn start(&mut self, us: u32);
+    /// Returns `true` once the started timeout has elapsed
+    fn is_expired(&mut self) -> bool;
+}
+
+/// [`Timeout`] counted by a `Timer16_x` in single mode
+pub struct TimerTimeout<TIM> {
+    tim: TIM,
+    clk: Hertz,
+}
+
+macro_rules! timer16_timeout {
+    ($($TIM:ident: $id:ident,)+) => {
+        $(
+            impl TimerTimeout<$TIM> {
+                pub fn new(tim: $TIM, clocks: &rcc::Clocks) -> Self {
+                    // NOTE(unsafe) atomic write to a stateless register
+                    let pm = unsafe { &(*Pm::ptr()) };
+                    $TIM::enable(pm);
+
+                    Self { tim, clk: clocks.timer16(rcc::Timer16Id::$id) }
+                }
+
+                pub fn release(self) -> $TIM {
+                    self.tim.cr().reset();
+                    self.tim
+                }
+            }
+
+            impl Timeout for TimerTimeout<$TIM> {
+                fn start(&mut self, us: u32) {
+                    let ticks = (us as u64 * self.clk.0 as u64 / 1_000_000).max(1);
+                    // Smallest prescaler for which the timeout fits into the counter
+                    let presc = (0..8).find(|&p| ticks >> p <= u16::MAX as u64).unwrap_or(7);
+                    let arr = (ticks >> presc).min(u16::MAX as u64) as u16;
+
+                    // The prescaler may only be changed while the timer is disabled
+                    self.tim.cr().reset();
+                    self.tim.cfgr().modify(|_, w| w.presc().bits(presc as u8));
+                    self.tim.cr().write(|w| w.enable().set_bit());
+
+                    self.tim.arr().write(|w| unsafe { w.arr().bits(arr) });
+                    while self.tim.isr().read().arrok().bit_is_clear() {}
+                    self.tim.icr().write(|w| w.arrrocf().set_bit().arrmcf().set_bit());
+
+                    self.tim.cr().modify(|_, w| w.sngstrt().set_bit());
+                }
+
+                fn is_expired(&mut self) -> bool {
+                    self.tim.isr().read().arrm().bit_is_set()
+                }
+            }
+        )+
+    };
+}
+
+timer16_timeout! {
+    Timer16_0: Timer16_0,
+    Timer16_1: Timer16_1,
+    Timer16_2: Timer16_2,
+}
+
+/// Byte level access shared by the master and the slave
+struct Bus<U, PINS, T> {
+    serial: Serial<U, PINS>,
+    rx: Rx<U>,
+    tx: Tx<U>,
+    timeout: T,
+    /// Duration of one bit in nanoseconds
+    bit_ns: u32,
+}
+
+impl<U, PINS, T> Bus<U, PINS, T>
+where
+    PINS: Pins<U>,
+    U: Instance,
+    T: Timeout,
+{
+    fn new(serial: Serial<U, PINS>, timeout: T, clocks: &rcc::Clocks) -> Self {
+        let brr = serial.usart.divider().read().brr().bits() as u64;
+        let bit_ns = (brr * 1_000_000_000 / clocks.apb_p().0 as u64) as u32;
+
+        Self {
+            serial,
+            rx: Rx { _usart: PhantomData },
+            tx: Tx { _usart: PhantomData },
+            timeout,
+            bit_ns,
+        }
+    }
+
+    /// Starts the timeout for the nominal duration of `bits`, plus the 40 % allowed by LIN
+    fn start_timeout(&mut self, bits: u32) {
+        let us = (bits as u64 * 14 * self.bit_ns as u64 / 10 / 1000) as u32;
+        self.timeout.start(us.max(1));
+    }
+
+    /// Drops received data and errors, including the line break flag
+    fn discard_rx(&mut self) {
+        while let Ok(_) | Err(nb::Error::Other(_)) = self.rx.read_with_status() {}
+        self.rx.is_line_break();
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        loop {
+            match self.rx.read_with_status() {
+                Ok((word, errors)) if errors.is_empty() => return Ok(word as u8),
+                Ok((_, errors)) | Err(nb::Error::Other(errors)) => return Err(Error::Receive(errors)),
+                Err(nb::Error::WouldBlock) => {
+                    if self.timeout.is_expired() {
+                        return Err(Error::Timeout);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends `byte` and checks its echo
+    fn write_byte(&mut self, byte: u8) -> Result<(), Error> {
+        // NOTE(unsafe) the transmit data register is only written by this bus
+        self.serial.usart.txdata().write(|w| unsafe { w.tdr().bits(byte as u16) });
+        if self.read_byte()? != byte {
+            return Err(Error::BitError);
+        }
+        Ok(())
+    }
+
+    /// Sends the data bytes followed by the checksum
+    fn write_response(&mut self, pid: u8, data: &[u8], model: ChecksumModel) -> Result<(), Error> {
+        self.start_timeout(10 * (data.len() as u32 + 1));
+        for &byte in data {
+            self.write_byte(byte)?;
+        }
+        self.write_byte(checksum(model, pid, data))
+    }
+
+    /// Receives `buf.len()` data bytes and checks the checksum
+    fn read_response(&mut self, pid: u8, buf: &mut [u8], model: ChecksumModel) -> Result<(), Error> {
+        self.start_timeout(10 * (buf.len() as u32 + 1));
+        for byte in buf.iter_mut() {
+            *byte = self.read_byte()?;
+        }
+        if self.read_byte()? != checksum(model, pid, buf) {
+            return Err(Error::Checksum);
+        }
+        Ok(())
+    }
+}
+
+/// Node sending the frame headers
+pub struct LinMaster<U, PINS, T> {
+    bus: Bus<U, PINS, T>,
+}
+
+impl<U, PINS, T> LinMaster<U, PINS, T>
+where
+    PINS: Pins<U>,
+    U: Instance,
+    T: Timeout,
+{
+    /// Creates a master on a serial configured for 8N1 at the bus baud rate
+    pub fn new(serial: Serial<U, PINS>, timeout: T, clocks: &rcc::Clocks) -> Self {
+        Self { bus: Bus::new(serial, timeout, clocks) }
+    }
+
+    /// Sends the break, the sync byte and the protected identifier of `id`
+    pub fn send_header(&mut self, id: u8) -> Result<(), Error> {
+        self.bus.discard_rx();
+        self.bus.tx.send_break();
+        // The break is received as a zero with a framing error
+        self.bus.discard_rx();
+
+        // The break is already out, the sync byte and the identifier remain
+        self.bus.start_timeout(20);
+        self.bus.write_byte(SYNC)?;
+        self.bus.write_byte(protected_id(id))
+    }
+
+    /// Sends the header of `id` followed by `data` as the response of the master
+    pub fn write_frame(&mut self, id: u8, data: &[u8], model: ChecksumModel) -> Result<(), Error> {
+        self.send_header(id)?;
+        self.bus.write_response(protected_id(id), data, model)
+    }
+
+    /// Sends the header of `id` and receives the response of a slave into `buf`
+    pub fn read_frame(&mut self, id: u8, buf: &mut [u8], model: ChecksumModel) -> Result<(), Error> {
+        self.send_header(id)?;
+        self.bus.read_response(protected_id(id), buf, model)
+    }
+
+    pub fn release(self) -> (Serial<U, PINS>, T) {
+        (self.bus.serial, self.bus.timeout)
+    }
+}
+
+/// Node answering the frame headers
+pub struct LinSlave<U, PINS, T> {
+    bus: Bus<U, PINS, T>,
+    /// Protected identifier of the last received header
+    pid: u8,
+}
+
+impl<U, PINS, T> LinSlave<U, PINS, T>
+where
+    PINS: Pins<U>,
+    U: Instance,
+    T: Timeout,
+{
+    /// Creates a slave on a serial configured for 8N1 at the bus baud rate
+    pub fn new(serial: Serial<U, PINS>, timeout: T, clocks: &rcc::Clocks) -> Self {
+        Self { bus: Bus::new(serial, timeout, clocks), pid: 0 }
+    }
+
+    /// Receives a header and returns its identifier
+    ///
+    /// Returns `WouldBlock` until a break is detected, then waits for the sync byte and the
+    /// protected identifier.
+    pub fn read_header(&mut self) -> nb::Result<u8, Error> {
+        if !self.bus.rx.is_line_break() {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.bus.discard_rx();
+
+        self.bus.start_timeout(20);
+        let mut sync = self.bus.read_byte();
+        // The break may still be in RXDATA if it ended after the flag was raised
+        if let Err(Error::Receive(errors)) = sync {
+            if errors.contains(RxErrors::FRAMING) {
+                sync = self.bus.read_byte();
+            }
+        }
+        if sync? != SYNC {
+            return Err(nb::Error::Other(Error::Sync));
+        }
+        let pid = self.bus.read_byte()?;
+        if protected_id(pid) != pid {
+            return Err(nb::Error::Other(Error::IdParity));
+        }
+
+        self.pid = pid;
+        Ok(pid & 0x3F)
+    }
+
+    /// Publishes `data` as the response to the last received header
+    pub fn respond(&mut self, data: &[u8], model: ChecksumModel) -> Result<(), Error> {
+        self.bus.write_response(self.pid, data, model)
+    }
+
+    /// Receives the response to the last received header into `buf`
+    pub fn read_response(&mut self, buf: &mut [u8], model: ChecksumModel) -> Result<(), Error> {
+        self.bus.read_response(self.pid, buf, model)
+    }
+
+    pub fn release(self) -> (Serial<U, PINS>, T) {
+        (self.bus.serial, self.bus.timeout)
+    }
+}
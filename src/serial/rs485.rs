@@ -0,0 +1,142 @@
+//! RS-485 half-duplex with a driver enable (DE) pin
+//!
+//! The USART has no hardware driver enable output, so DE is a GPIO asserted before the
+//! first start bit and released after transmission complete (`TC`) of the last frame.
+
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal_nb::serial::{ErrorKind, ErrorType, Read};
+
+use super::{us_cycles, Instance, Pins, Rx, Serial};
+use crate::rcc;
+
+/// RS-485 driver enable configuration
+pub struct Rs485Config {
+    /// Time between asserting DE and the first start bit, in microseconds
+    pub assertion_time_us: u32,
+    /// Time between the end of the last stop bit and releasing DE, in microseconds
+    pub deassertion_time_us: u32,
+    /// DE is asserted by driving the pin low
+    pub de_active_low: bool,
+}
+
+impl Default for Rs485Config {
+    fn default() -> Self {
+        Self {
+            assertion_time_us: 0,
+            deassertion_time_us: 0,
+            de_active_low: false,
+        }
+    }
+}
+
+/// Serial driving an RS-485 transceiver
+pub struct Rs485<U, PINS, DE> {
+    serial: Serial<U, PINS>,
+    rx: Rx<U>,
+    de: DE,
+    de_active_low: bool,
+    assertion_cycles: u32,
+    deassertion_cycles: u32,
+}
+
+impl<U, PINS> Serial<U, PINS>
+where
+    PINS: Pins<U>,
+    U: Instance,
+{
+    /// Switches the serial to RS-485 mode with `de` as the driver enable pin
+    pub fn rs485<DE: OutputPin<Error = Infallible>>(
+        self,
+        de: DE,
+        config: Rs485Config,
+        clocks: &rcc::Clocks,
+    ) -> Rs485<U, PINS, DE> {
+        let mut rs485 = Rs485 {
+            serial: self,
+            rx: Rx { _usart: PhantomData },
+            de,
+            de_active_low: config.de_active_low,
+            assertion_cycles: us_cycles(clocks, config.assertion_time_us),
+            deassertion_cycles: us_cycles(clocks, config.deassertion_time_us),
+        };
+        rs485.set_de(false);
+        rs485
+    }
+}
+
+impl<U, PINS, DE> Rs485<U, PINS, DE>
+where
+    PINS: Pins<U>,
+    U: Instance,
+    DE: OutputPin<Error = Infallible>,
+{
+    fn set_de(&mut self, asserted: bool) {
+        let _ = if asserted != self.de_active_low {
+            self.de.set_high()
+        } else {
+            self.de.set_low()
+        };
+    }
+
+    /// Transmits `bytes` as one block, keeping DE asserted between frames
+    pub fn transmit(&mut self, bytes: &[u8]) {
+        self.set_de(true);
+        riscv::asm::delay(self.assertion_cycles);
+
+        let usart = &self.serial.usart;
+
+        for &byte in bytes {
+            while usart.flags().read().txe().bit_is_clear() {}
+            usart.txdata().write(|w| unsafe { w.tdr().bits(byte as u16) });
+        }
+        while usart.flags().read().tc().bit_is_clear() {}
+
+        riscv::asm::delay(self.deassertion_cycles);
+        self.set_de(false);
+    }
+
+    pub fn release(mut self) -> (Serial<U, PINS>, DE) {
+        self.set_de(false);
+        (self.serial, self.de)
+    }
+}
+
+impl<U, PINS, DE> ErrorType for Rs485<U, PINS, DE>
+where
+    U: Instance,
+{
+    type Error = ErrorKind;
+}
+
+impl<U, PINS, DE> Read<u8> for Rs485<U, PINS, DE>
+where
+    U: Instance,
+{
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.rx.read()
+    }
+}
+
+impl<U, PINS, DE> embedded_io::ErrorType for Rs485<U, PINS, DE> {
+    type Error = Infallible;
+}
+
+impl<U, PINS, DE> embedded_io::Write for Rs485<U, PINS, DE>
+where
+    PINS: Pins<U>,
+    U: Instance,
+    DE: OutputPin<Error = Infallible>,
+{
+    /// Transmits the whole buffer, DE is released when this returns
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.transmit(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -85,3 +85,26 @@ impl Div<Hertz> for Hertz {
     }
 }
 
+
+/// Bits per second
+#[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Debug)]
+pub struct Bps(pub u32);
+
+impl Bps {
+    /// Create a `Bps` from the given bits per second.
+    pub const fn bps(bps: u32) -> Self {
+        Self(bps)
+    }
+}
+
+/// This is a convenience shortcut for [`Bps::bps`]
+pub const fn bps(bps: u32) -> Bps {
+    Bps::bps(bps)
+}
+
+impl Div<Bps> for Hertz {
+    type Output = u32;
+    fn div(self, rhs: Bps) -> Self::Output {
+        self.0 / rhs.0
+    }
+}